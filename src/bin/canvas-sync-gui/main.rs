@@ -2,7 +2,8 @@ use std::path::PathBuf;
 
 use canvas_lms_sync::{
     canvas_api::Client,
-    sync::{download_files, SyncConfig},
+    layout::Layout,
+    sync::{execute_plan, plan_sync, SyncConfig, SyncMode},
 };
 use eframe::{
     egui::{CentralPanel, Frame, Margin, RichText, ScrollArea, TopBottomPanel},
@@ -32,6 +33,7 @@ pub struct RemoteConfig {
 pub enum SyncType {
     Modules,
     Files,
+    Both,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -72,33 +74,23 @@ pub async fn sync(remote: &RemoteConfig, course: &CourseConfig) {
     info!("Syncing course {}...", course.course_id);
     let client = Client::new(remote.host.clone(), remote.token.clone());
     let mut downloader = canvas_lms_sync::download::Downloader::new(reqwest::Client::new(), 4);
-    match course.sync_type {
-        SyncType::Files => {
-            download_files(
-                &SyncConfig {
-                    courseid: course.course_id.parse().unwrap(),
-                    path: course.folder.clone(),
-                },
-                &client,
-                &downloader,
-            )
-            .await
-        }
-        SyncType::Modules => {
-            canvas_lms_sync::sync::download_modules(
-                &SyncConfig {
-                    courseid: course.course_id.parse().unwrap(),
-                    path: course.folder.clone(),
-                },
-                &client,
-                &downloader,
-            )
-            .await
-        }
-    }
+    let config = SyncConfig {
+        courseid: course.course_id.parse().unwrap(),
+        path: course.folder.clone(),
+        mode: match course.sync_type {
+            SyncType::Files => SyncMode::Files,
+            SyncType::Modules => SyncMode::Modules,
+            SyncType::Both => SyncMode::Both,
+        },
+        prune: None,
+        markdown: false,
+        discussions: false,
+        layout: Layout::default(),
+    };
+    let mut report = execute_plan(plan_sync(&config, &client).await, &downloader);
     info!("Waiting for all downloads to finish...");
-    downloader.finish().await;
-    info!("Done!");
+    report.merge(downloader.finish().await);
+    info!("Done! {}", report);
 }
 
 impl App for CanvasSyncApp {
@@ -186,6 +178,7 @@ impl App for CanvasSyncApp {
                     ui.label("Sync Type");
                     ui.radio_value(&mut course.sync_type, SyncType::Files, "Files");
                     ui.radio_value(&mut course.sync_type, SyncType::Modules, "Modules");
+                    ui.radio_value(&mut course.sync_type, SyncType::Both, "Both");
                     ui.label("Folder");
                     if ui.button("Select Folder").clicked() {
                         let mut dialog = FileDialog::select_folder(None);
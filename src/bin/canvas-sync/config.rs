@@ -0,0 +1,230 @@
+use std::{
+    collections::HashSet,
+    error::Error,
+    path::{Path, PathBuf},
+};
+
+use canvas_lms_sync::{
+    canvas_api::{rate_limit::RateLimitConfig, Client},
+    courses::{course_folder_name, discover_courses, CourseFilter},
+    layout::Layout,
+    report::SyncReport,
+    retry::RetryPolicy,
+    sync::{PruneMode, SyncConfig, SyncMode},
+};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+/// A course to sync, with optional overrides of the top-level settings.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CourseConfig {
+    pub id: i64,
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    #[serde(default)]
+    pub mode: Option<SyncMode>,
+    #[serde(default)]
+    pub prune: Option<PruneMode>,
+    #[serde(default)]
+    pub markdown: Option<bool>,
+    #[serde(default)]
+    pub discussions: Option<bool>,
+    #[serde(default)]
+    pub layout: Option<Layout>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RemoteConfig {
+    pub host: String,
+    pub token: String,
+    #[serde(default)]
+    pub courses: Vec<CourseConfig>,
+    #[serde(default)]
+    pub all_courses: bool,
+    #[serde(default)]
+    pub filter: CourseFilter,
+    #[serde(default)]
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Config {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    host: Option<String>,
+    #[serde(default)]
+    courseid: Option<i64>,
+    #[serde(default)]
+    usemodules: bool,
+    #[serde(default)]
+    all_courses: bool,
+    #[serde(default)]
+    filter: CourseFilter,
+    #[serde(default)]
+    pub remotes: Vec<RemoteConfig>,
+    #[serde(default)]
+    mode: Option<SyncMode>,
+    #[serde(default)]
+    prune: Option<PruneMode>,
+    #[serde(default)]
+    markdown: bool,
+    #[serde(default)]
+    discussions: bool,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(skip)]
+    only_course: Option<i64>,
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+impl Config {
+    pub fn read_from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let file = std::fs::File::open(path)?;
+        let mut config: Config = serde_yaml::from_reader(file)?;
+        config.merge_legacy_remote()?;
+        if config.remotes.is_empty() {
+            return Err("no remotes configured".into());
+        }
+        config.layout.validate()?;
+        for remote in &config.remotes {
+            for course in &remote.courses {
+                if let Some(layout) = &course.layout {
+                    layout
+                        .validate()
+                        .map_err(|e| format!("course {}: {}", course.id, e))?;
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Turns the old single course `host`/`token`/`courseid` keys into a remote.
+    fn merge_legacy_remote(&mut self) -> Result<(), Box<dyn Error>> {
+        let (host, token) = match (self.host.take(), self.token.take()) {
+            (Some(host), Some(token)) => (host, token),
+            (None, None) => return Ok(()),
+            _ => return Err("both host and token must be set".into()),
+        };
+        let courses = self
+            .courseid
+            .map(|id| CourseConfig {
+                id,
+                ..Default::default()
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        if courses.is_empty() && !self.all_courses {
+            return Err("either courseid or all_courses must be set".into());
+        }
+        self.remotes.insert(
+            0,
+            RemoteConfig {
+                host,
+                token,
+                courses,
+                all_courses: self.all_courses,
+                filter: self.filter.clone(),
+                path: PathBuf::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Puts every remote's folder under `dest`.
+    pub fn set_dest(&mut self, dest: &Path) {
+        for remote in &mut self.remotes {
+            remote.path = dest.join(&remote.path);
+        }
+    }
+
+    /// Restricts syncing to one course, adding it to the first remote if it isn't configured.
+    pub fn set_only_course(&mut self, id: i64) {
+        let listed = self
+            .remotes
+            .iter()
+            .any(|r| r.courses.iter().any(|c| c.id == id));
+        let discovered = self.remotes.iter().any(|r| r.all_courses);
+        if !listed && !discovered {
+            self.remotes[0].courses.push(CourseConfig {
+                id,
+                ..Default::default()
+            });
+        }
+        self.only_course = Some(id);
+    }
+
+    pub fn client(&self, remote: &RemoteConfig) -> Client {
+        Client::new(remote.host.clone(), remote.token.clone())
+            .with_retry_policy(self.retry.clone())
+            .with_rate_limit(self.rate_limit.clone())
+    }
+
+    fn sync_config(&self, course: &CourseConfig, path: PathBuf) -> SyncConfig {
+        SyncConfig {
+            courseid: course.id,
+            path,
+            mode: match course.mode.or(self.mode) {
+                Some(mode) => mode,
+                None if self.usemodules => SyncMode::Modules,
+                None => SyncMode::Files,
+            },
+            prune: course.prune.or(self.prune),
+            markdown: course.markdown.unwrap_or(self.markdown),
+            discussions: course.discussions.unwrap_or(self.discussions),
+            layout: course.layout.clone().unwrap_or(self.layout.clone()),
+        }
+    }
+
+    /// The courses to sync for a remote, listed ones first, then discovered ones.
+    pub async fn sync_configs(
+        &self,
+        remote: &RemoteConfig,
+        client: &Client,
+        report: &mut SyncReport,
+    ) -> Vec<SyncConfig> {
+        let mut configs = Vec::new();
+        let mut ids = HashSet::new();
+        let wanted = |id: i64| self.only_course.is_none_or(|only| only == id);
+        for course in &remote.courses {
+            if wanted(course.id) && ids.insert(course.id) {
+                let path = remote.path.join(course.path.clone().unwrap_or_default());
+                configs.push(self.sync_config(course, path));
+            }
+        }
+        if remote.all_courses && (self.only_course.is_none() || configs.is_empty()) {
+            match discover_courses(client, &remote.filter).await {
+                Ok(courses) => {
+                    info!("Found {} courses on {}", courses.len(), remote.host);
+                    for course in courses {
+                        if !wanted(course.id) || !ids.insert(course.id) {
+                            continue;
+                        }
+                        let path = remote
+                            .path
+                            .join(course_folder_name(&course, self.layout.sanitize));
+                        let course = CourseConfig {
+                            id: course.id,
+                            ..Default::default()
+                        };
+                        configs.push(self.sync_config(&course, path));
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to list courses on {}: {}", remote.host, e);
+                    report.fail(format!("listing courses on {}", remote.host), e);
+                }
+            }
+        }
+        configs
+    }
+}
@@ -1,55 +1,325 @@
-use std::{error::Error, path::PathBuf};
+use std::{collections::HashSet, path::PathBuf};
 
 use canvas_lms_sync::{
-    canvas_api::Client,
+    courses::{course_folder_name, discover_courses, CourseFilter},
     download::Downloader,
-    sync::{download_files, download_modules, SyncConfig},
+    report::SyncReport,
+    status::course_status,
+    sync::{execute_plan, plan_sync, SyncMode},
+    tree::{files_tree, modules_tree, TreeNode},
 };
-use log::info;
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Deserialize, Serialize)]
-pub struct Config {
-    token: String,
-    host: String,
-    courseid: i64,
-    usemodules: bool,
+use clap::{Args, Parser, Subcommand};
+use config::Config;
+use log::{error, info, LevelFilter};
+use serde::Serialize;
+
+mod config;
+
+const EXAMPLE_CONFIG: &str = include_str!("../../../canvas-sync.example.yml");
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path of the configuration file
+    #[arg(short, long, global = true, default_value = "canvas-sync.yml")]
+    config: PathBuf,
+    /// Directory to sync into instead of the current directory
+    #[arg(short, long, global = true)]
+    dest: Option<PathBuf>,
+    /// Number of parallel downloads
+    #[arg(short = 'j', long, global = true)]
+    concurrency: Option<usize>,
+    /// Log level: off, error, warn, info, debug or trace
+    #[arg(long, global = true)]
+    log_level: Option<LevelFilter>,
+    /// Only work on the course with this id
+    #[arg(long, global = true)]
+    course: Option<i64>,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    sync: SyncArgs,
 }
 
-impl Into<SyncConfig> for Config {
-    fn into(self) -> SyncConfig {
-        SyncConfig {
-            courseid: self.courseid,
-            path: PathBuf::new(),
-        }
+#[derive(Debug, Args)]
+struct SyncArgs {
+    /// Print what would be synced without downloading anything
+    #[arg(long, global = true)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+struct LsArgs {
+    /// List the files section, by default chosen by each course's sync mode
+    #[arg(long)]
+    files: bool,
+    /// List the modules, by default chosen by each course's sync mode
+    #[arg(long)]
+    modules: bool,
+    /// Print JSON instead of a tree
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct StatusArgs {
+    /// Print JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Sync the configured courses (the default)
+    Sync,
+    /// List the active courses on the configured remotes
+    Courses,
+    /// Show the files and modules of the configured courses on Canvas
+    #[command(visible_alias = "tree")]
+    Ls(LsArgs),
+    /// Compare the local copies with Canvas without downloading anything
+    Status(StatusArgs),
+    /// Manage the configuration file
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Write an example configuration file
+    Init {
+        /// Overwrite an existing configuration file
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+fn init_logger(level: Option<LevelFilter>) {
+    let mut builder = env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("canvas_lms_sync=info,canvas_sync=info"),
+    );
+    if let Some(level) = level {
+        builder
+            .filter_module("canvas_lms_sync", level)
+            .filter_module("canvas_sync", level);
     }
+    builder.init();
 }
 
-impl Config {
-    pub fn read_from_path<P: AsRef<std::path::Path>>(path: P) -> Result<Self, Box<dyn Error>> {
-        let file = std::fs::File::open(path)?;
-        let config = serde_yaml::from_reader(file)?;
-        Ok(config)
+fn load_config(cli: &Cli) -> Config {
+    let mut config = Config::read_from_path(&cli.config).expect("Failed to read config file");
+    if let Some(dest) = &cli.dest {
+        config.set_dest(dest);
     }
+    if let Some(concurrency) = cli.concurrency {
+        config.concurrency = concurrency;
+    }
+    if let Some(course) = cli.course {
+        config.set_only_course(course);
+    }
+    config
 }
 
-#[tokio::main]
-async fn main() {
-    if std::env::var("RUST_LOG").is_err() {
-        std::env::set_var("RUST_LOG", "canvas_lms_sync=info");
+fn init_config(cli: &Cli, force: bool) {
+    if cli.config.exists() && !force {
+        error!(
+            "{:?} already exists, pass --force to overwrite it",
+            cli.config
+        );
+        std::process::exit(1);
+    }
+    std::fs::write(&cli.config, EXAMPLE_CONFIG).expect("Failed to write config file");
+    println!(
+        "Wrote {:?}, edit it to add your token and courses",
+        cli.config
+    );
+}
+
+async fn list_courses(cli: &Cli) {
+    let config = load_config(cli);
+    let mut failed = false;
+    for remote in &config.remotes {
+        let client = config.client(remote);
+        match discover_courses(&client, &CourseFilter::default()).await {
+            Ok(courses) => {
+                println!("{}", remote.host);
+                for course in courses {
+                    let synced = remote.courses.iter().any(|c| c.id == course.id)
+                        || (remote.all_courses && remote.filter.matches(&course));
+                    println!(
+                        "{}{:>8}  {}  {}",
+                        if synced { "* " } else { "  " },
+                        course.id,
+                        course_folder_name(&course, config.layout.sanitize),
+                        course.name.as_deref().unwrap_or("")
+                    );
+                }
+            }
+            Err(e) => {
+                error!("Failed to list courses on {}: {}", remote.host, e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct CourseTree {
+    course: i64,
+    path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<TreeNode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modules: Option<TreeNode>,
+}
+
+async fn list_tree(cli: &Cli, args: &LsArgs) {
+    let config = load_config(cli);
+    let mut report = SyncReport::default();
+    let mut trees = Vec::new();
+    for remote in &config.remotes {
+        let client = config.client(remote);
+        for sync_config in config.sync_configs(remote, &client, &mut report).await {
+            let (files, modules) = if args.files || args.modules {
+                (args.files, args.modules)
+            } else {
+                (
+                    sync_config.mode != SyncMode::Modules,
+                    sync_config.mode != SyncMode::Files,
+                )
+            };
+            let mut tree = CourseTree {
+                course: sync_config.courseid,
+                path: sync_config.path.clone(),
+                files: None,
+                modules: None,
+            };
+            if files {
+                match files_tree(&sync_config, &client).await {
+                    Ok(files) => tree.files = Some(files),
+                    Err(e) => report.fail(format!("listing files of {}", tree.course), e),
+                }
+            }
+            if modules {
+                match modules_tree(&sync_config, &client).await {
+                    Ok(modules) => tree.modules = Some(modules),
+                    Err(e) => report.fail(format!("listing modules of {}", tree.course), e),
+                }
+            }
+            trees.push(tree);
+        }
     }
-    env_logger::init();
 
-    let config = Config::read_from_path("canvas-sync.yml").expect("Failed to read config file");
-    let client = Client::new(config.host.clone(), config.token.clone());
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&trees).expect("Failed to serialize tree")
+        );
+    } else {
+        for tree in &trees {
+            println!("course {} -> {:?}", tree.course, tree.path);
+            for node in tree.files.iter().chain(tree.modules.iter()) {
+                print!("{}", node);
+            }
+        }
+    }
+    if report.has_failures() {
+        eprint!("{}", report);
+        std::process::exit(1);
+    }
+}
 
-    let mut downloader = Downloader::new(reqwest::Client::new(), 4);
+async fn show_status(cli: &Cli, args: &StatusArgs) {
+    let config = load_config(cli);
+    let mut report = SyncReport::default();
+    let mut statuses = Vec::new();
+    for remote in &config.remotes {
+        let client = config.client(remote);
+        for sync_config in config.sync_configs(remote, &client, &mut report).await {
+            statuses.push(course_status(&sync_config, &client).await);
+        }
+    }
 
-    if config.usemodules {
-        download_modules(&config.into(), &client, &downloader).await;
+    if args.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&statuses).expect("Failed to serialize status")
+        );
     } else {
-        download_files(&config.into(), &client, &downloader).await;
-    };
+        for status in &statuses {
+            print!("{}", status);
+        }
+    }
+    if report.has_failures() || statuses.iter().any(|s| !s.failed.is_empty()) {
+        eprint!("{}", report);
+        std::process::exit(1);
+    }
+}
+
+async fn sync(cli: &Cli, args: &SyncArgs) {
+    let config = load_config(cli);
+
+    let mut report = SyncReport::default();
+    let mut plans = Vec::new();
+    let mut paths = HashSet::new();
+    for remote in &config.remotes {
+        let client = config.client(remote);
+        for sync_config in config.sync_configs(remote, &client, &mut report).await {
+            // Courses sharing a folder would share sync state and prune each other's files.
+            if !paths.insert(sync_config.path.clone()) {
+                error!(
+                    "Skipping course {}: folder {:?} is already used by another course",
+                    sync_config.courseid, sync_config.path
+                );
+                report.fail(
+                    format!("syncing course {}", sync_config.courseid),
+                    format!("folder {:?} is already in use", sync_config.path),
+                );
+                continue;
+            }
+            info!(
+                "Planning course {} into {:?}",
+                sync_config.courseid, sync_config.path
+            );
+            let plan = plan_sync(&sync_config, &client).await;
+            plans.push((sync_config, plan));
+        }
+    }
+
+    if args.dry_run {
+        for (sync_config, plan) in &plans {
+            println!("course {} -> {:?}", sync_config.courseid, sync_config.path);
+            for action in &plan.actions {
+                println!("{}", action);
+            }
+            println!(
+                "{} actions, {} bytes to download",
+                plan.actions.len(),
+                plan.total_bytes()
+            );
+            report.failed.extend(plan.report.failed.iter().cloned());
+        }
+        for failure in &report.failed {
+            println!("failed {}: {}", failure.what, failure.error);
+        }
+        if report.has_failures() {
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut downloader = Downloader::with_retry_policy(
+        reqwest::Client::new(),
+        config.concurrency,
+        config.retry.clone(),
+    );
+
+    for (_, plan) in plans {
+        report.merge(execute_plan(plan, &downloader));
+    }
 
     info!("Waiting for downloads to finish...");
     let mut ticker = tokio::time::interval(std::time::Duration::from_secs(1));
@@ -64,10 +334,30 @@ async fn main() {
                     }
                 }
             }
-            _ = downloader.finish() => {
+            downloads = downloader.finish() => {
                 info!("Downloads finished");
+                report.merge(downloads);
                 break;
             }
         }
     }
+
+    print!("{}", report);
+    if report.has_failures() {
+        std::process::exit(1);
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    init_logger(cli.log_level);
+
+    match &cli.command {
+        None | Some(Command::Sync) => sync(&cli, &cli.sync).await,
+        Some(Command::Courses) => list_courses(&cli).await,
+        Some(Command::Ls(args)) => list_tree(&cli, args).await,
+        Some(Command::Status(args)) => show_status(&cli, args).await,
+        Some(Command::Config(ConfigCommand::Init { force })) => init_config(&cli, *force),
+    }
 }
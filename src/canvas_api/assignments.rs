@@ -0,0 +1,59 @@
+use super::{Client, Error};
+use async_stream::stream;
+use serde::Deserialize;
+use tokio_stream::Stream;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssignmentResp {
+    pub id: i64,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub due_at: Option<String>,
+    pub lock_at: Option<String>,
+    pub unlock_at: Option<String>,
+    pub points_possible: Option<f64>,
+    pub submission_types: Vec<String>,
+    pub html_url: String,
+    pub position: Option<i64>,
+    pub published: Option<bool>,
+    pub locked_for_user: Option<bool>,
+    pub lock_explanation: Option<String>,
+}
+
+impl Client {
+    pub fn list_assignments(
+        &self,
+        courseid: i64,
+    ) -> impl Stream<Item = Result<AssignmentResp, Error>> + '_ {
+        let url = self.build_url(&format!("/api/v1/courses/{}/assignments", courseid));
+        stream! {
+            let mut next = Some(url);
+            while let Some(url) = next {
+                let (resp, pagination) = self.make_json_request::<Vec<AssignmentResp>, _>(url).await?;
+
+                next = pagination.and_then(|p| p.next);
+
+                for assignment in resp {
+                    yield Ok(assignment);
+                }
+
+            }
+        }
+    }
+    pub async fn get_course_assignment(
+        &self,
+        courseid: i64,
+        assignmentid: i64,
+    ) -> Result<AssignmentResp, Error> {
+        let url = self.build_url(&format!(
+            "/api/v1/courses/{}/assignments/{}",
+            courseid, assignmentid
+        ));
+
+        let (data, _) = self.make_json_request::<AssignmentResp, _>(url).await?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,49 @@
+use super::{Client, Error};
+use async_stream::stream;
+use serde::Deserialize;
+use tokio_stream::Stream;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TermResp {
+    pub id: i64,
+    pub name: String,
+    pub start_at: Option<String>,
+    pub end_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CourseResp {
+    pub id: i64,
+    pub name: Option<String>,
+    pub course_code: Option<String>,
+    pub workflow_state: Option<String>,
+    pub enrollment_term_id: Option<i64>,
+    pub term: Option<TermResp>,
+    pub access_restricted_by_date: Option<bool>,
+}
+
+impl Client {
+    pub fn list_courses(&self) -> impl Stream<Item = Result<CourseResp, Error>> + '_ {
+        let url = self.build_url("/api/v1/courses?enrollment_state=active&include[]=term");
+        stream! {
+            let mut next = Some(url);
+            while let Some(url) = next {
+                let (resp, pagination) = self.make_json_request::<Vec<CourseResp>, _>(url).await?;
+
+                next = pagination.and_then(|p| p.next);
+
+                for course in resp {
+                    yield Ok(course);
+                }
+
+            }
+        }
+    }
+    pub async fn get_course(&self, courseid: i64) -> Result<CourseResp, Error> {
+        let url = self.build_url(&format!("/api/v1/courses/{}?include[]=term", courseid));
+
+        let (data, _) = self.make_json_request::<CourseResp, _>(url).await?;
+
+        Ok(data)
+    }
+}
@@ -0,0 +1,135 @@
+use super::{Client, Error};
+use async_stream::stream;
+use serde::Deserialize;
+use tokio_stream::Stream;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttachmentResp {
+    pub id: i64,
+    pub display_name: String,
+    #[serde(rename = "content-type", default)]
+    pub content_type: String,
+    pub url: String,
+    pub size: i64,
+    pub created_at: String,
+    pub updated_at: String,
+    pub modified_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuthorResp {
+    pub id: Option<i64>,
+    pub display_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscussionTopicResp {
+    pub id: i64,
+    pub title: String,
+    pub message: Option<String>,
+    pub html_url: String,
+    pub posted_at: Option<String>,
+    pub last_reply_at: Option<String>,
+    pub user_name: Option<String>,
+    pub author: Option<AuthorResp>,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentResp>,
+    pub locked_for_user: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ParticipantResp {
+    pub id: i64,
+    pub display_name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscussionEntryResp {
+    pub id: i64,
+    pub user_id: Option<i64>,
+    pub parent_id: Option<i64>,
+    pub created_at: String,
+    pub updated_at: Option<String>,
+    pub message: Option<String>,
+    #[serde(default)]
+    pub deleted: bool,
+    pub attachment: Option<AttachmentResp>,
+    #[serde(default)]
+    pub replies: Vec<DiscussionEntryResp>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscussionViewResp {
+    #[serde(default)]
+    pub participants: Vec<ParticipantResp>,
+    #[serde(default)]
+    pub view: Vec<DiscussionEntryResp>,
+}
+
+impl Client {
+    fn list_topics(
+        &self,
+        url: String,
+    ) -> impl Stream<Item = Result<DiscussionTopicResp, Error>> + '_ {
+        stream! {
+            let mut next = Some(url);
+            while let Some(url) = next {
+                let (resp, pagination) = self.make_json_request::<Vec<DiscussionTopicResp>, _>(url).await?;
+
+                next = pagination.and_then(|p| p.next);
+
+                for topic in resp {
+                    yield Ok(topic);
+                }
+
+            }
+        }
+    }
+    pub fn list_discussion_topics(
+        &self,
+        courseid: i64,
+    ) -> impl Stream<Item = Result<DiscussionTopicResp, Error>> + '_ {
+        self.list_topics(self.build_url(&format!("/api/v1/courses/{}/discussion_topics", courseid)))
+    }
+    pub fn list_announcements(
+        &self,
+        courseid: i64,
+    ) -> impl Stream<Item = Result<DiscussionTopicResp, Error>> + '_ {
+        self.list_topics(self.build_url(&format!(
+            "/api/v1/courses/{}/discussion_topics?only_announcements=true",
+            courseid
+        )))
+    }
+    pub async fn get_discussion_view(
+        &self,
+        courseid: i64,
+        topicid: i64,
+    ) -> Result<DiscussionViewResp, Error> {
+        let url = self.build_url(&format!(
+            "/api/v1/courses/{}/discussion_topics/{}/view",
+            courseid, topicid
+        ));
+
+        let (data, _) = self.make_json_request::<DiscussionViewResp, _>(url).await?;
+
+        Ok(data)
+    }
+}
+
+impl DiscussionViewResp {
+    pub fn author(&self, user_id: Option<i64>) -> Option<&str> {
+        let user_id = user_id?;
+        self.participants
+            .iter()
+            .find(|p| p.id == user_id)
+            .map(|p| p.display_name.as_str())
+    }
+    pub fn entries(&self) -> impl Iterator<Item = &DiscussionEntryResp> {
+        let mut stack = self.view.iter().rev().collect::<Vec<_>>();
+        std::iter::from_fn(move || {
+            let entry = stack.pop()?;
+            stack.extend(entry.replies.iter().rev());
+            Some(entry)
+        })
+    }
+}
@@ -0,0 +1,218 @@
+use std::fmt::Display;
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::retry::{RetryPolicy, Retryable};
+
+const SNIPPET_LEN: usize = 512;
+
+#[derive(Debug, Deserialize)]
+pub struct ApiError {
+    pub errors: Vec<ApiErrorDetail>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ApiErrorDetail {
+    pub message: String,
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages = self
+            .errors
+            .iter()
+            .map(|e| e.message.as_str())
+            .collect::<Vec<_>>();
+        write!(f, "{}", messages.join("; "))
+    }
+}
+
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub url: String,
+    pub body: String,
+    pub api_error: Option<ApiError>,
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} from {}", self.status, self.url)?;
+        match &self.api_error {
+            Some(api_error) => write!(f, ": {}", api_error),
+            None if !self.body.trim().is_empty() => write!(f, ": {}", self.body.trim()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Unauthorized(HttpError),
+    Forbidden(HttpError),
+    NotFound(HttpError),
+    RateLimited(HttpError),
+    ServerError(HttpError),
+    UnexpectedStatus(HttpError),
+    DecodeError {
+        url: String,
+        body: String,
+        source: serde_json::Error,
+    },
+    ReqwestError(reqwest::Error),
+}
+
+pub(super) fn snippet(body: &str) -> String {
+    match body.char_indices().nth(SNIPPET_LEN) {
+        Some((idx, _)) => format!("{}...", &body[..idx]),
+        None => body.to_string(),
+    }
+}
+
+impl Error {
+    pub fn from_response(status: StatusCode, url: String, body: &str) -> Self {
+        let error = HttpError {
+            status,
+            url,
+            body: snippet(body),
+            api_error: serde_json::from_str(body).ok(),
+        };
+        match status {
+            StatusCode::UNAUTHORIZED => Error::Unauthorized(error),
+            StatusCode::FORBIDDEN if body.contains("Rate Limit Exceeded") => {
+                Error::RateLimited(error)
+            }
+            StatusCode::FORBIDDEN => Error::Forbidden(error),
+            StatusCode::NOT_FOUND => Error::NotFound(error),
+            StatusCode::TOO_MANY_REQUESTS => Error::RateLimited(error),
+            status if status.is_server_error() => Error::ServerError(error),
+            _ => Error::UnexpectedStatus(error),
+        }
+    }
+    pub fn http_error(&self) -> Option<&HttpError> {
+        match self {
+            Error::Unauthorized(e)
+            | Error::Forbidden(e)
+            | Error::NotFound(e)
+            | Error::RateLimited(e)
+            | Error::ServerError(e)
+            | Error::UnexpectedStatus(e) => Some(e),
+            Error::DecodeError { .. } => None,
+            Error::ReqwestError(_) => None,
+        }
+    }
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::ReqwestError(e) => e.status(),
+            _ => self.http_error().map(|e| e.status),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Unauthorized(e) => {
+                write!(f, "unauthorized, check your access token ({})", e)
+            }
+            Error::Forbidden(e) => write!(f, "access denied ({})", e),
+            Error::NotFound(e) => write!(f, "not found ({})", e),
+            Error::RateLimited(e) => write!(f, "rate limited by Canvas ({})", e),
+            Error::ServerError(e) => write!(f, "server error ({})", e),
+            Error::UnexpectedStatus(e) => write!(f, "unexpected response ({})", e),
+            Error::DecodeError { url, body, source } => write!(
+                f,
+                "invalid response from {}: {} (body: {})",
+                url,
+                source,
+                body.trim()
+            ),
+            Error::ReqwestError(e) => write!(f, "request failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::DecodeError { source, .. } => Some(source),
+            Error::ReqwestError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Retryable for Error {
+    fn is_retryable(&self, policy: &RetryPolicy) -> bool {
+        match self {
+            Error::RateLimited(_) => true,
+            Error::DecodeError { .. } => false,
+            Error::ReqwestError(e) => e.is_retryable(policy),
+            _ => self
+                .status()
+                .map(|status| policy.is_retryable_status(status))
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_status(status: u16, body: &str) -> Error {
+        Error::from_response(
+            StatusCode::from_u16(status).unwrap(),
+            "https://canvas.example/api/v1/courses".to_string(),
+            body,
+        )
+    }
+
+    #[test]
+    fn statuses_are_classified() {
+        let body = r#"{"errors":[{"message":"nope"}]}"#;
+        assert!(matches!(from_status(401, body), Error::Unauthorized(_)));
+        assert!(matches!(from_status(403, body), Error::Forbidden(_)));
+        assert!(matches!(from_status(404, body), Error::NotFound(_)));
+        assert!(matches!(from_status(429, body), Error::RateLimited(_)));
+        assert!(matches!(from_status(500, body), Error::ServerError(_)));
+        assert!(matches!(from_status(503, body), Error::ServerError(_)));
+        assert!(matches!(from_status(400, body), Error::UnexpectedStatus(_)));
+    }
+
+    #[test]
+    fn throttled_403_is_rate_limited() {
+        let error = from_status(403, "403 Forbidden (Rate Limit Exceeded)\n");
+        assert!(matches!(error, Error::RateLimited(_)));
+        assert!(error.is_retryable(&RetryPolicy::default()));
+        assert!(!from_status(403, "").is_retryable(&RetryPolicy::default()));
+    }
+
+    #[test]
+    fn api_errors_are_parsed() {
+        let error = from_status(404, r#"{"errors":[{"message":"a"},{"message":"b"}]}"#);
+        let http = error.http_error().unwrap();
+        assert_eq!(http.api_error.as_ref().unwrap().to_string(), "a; b");
+        assert!(error.to_string().ends_with(": a; b)"), "{}", error);
+    }
+
+    #[test]
+    fn non_json_body_keeps_status_and_snippet() {
+        let body = format!("<html>{}</html>", "x".repeat(2000));
+        let error = from_status(502, &body);
+        assert_eq!(error.status(), Some(StatusCode::BAD_GATEWAY));
+        let http = error.http_error().unwrap();
+        assert!(http.api_error.is_none());
+        assert!(http.body.starts_with("<html>xxx"));
+        assert!(http.body.ends_with("..."));
+        assert_eq!(http.body.chars().count(), SNIPPET_LEN + 3);
+    }
+
+    #[test]
+    fn snippet_cuts_at_char_boundaries() {
+        let body = "é".repeat(SNIPPET_LEN + 1);
+        assert_eq!(snippet(&body), format!("{}...", "é".repeat(SNIPPET_LEN)));
+        assert_eq!(snippet("short"), "short");
+    }
+}
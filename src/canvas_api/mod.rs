@@ -1,25 +1,28 @@
 use once_cell::sync::Lazy;
 use regex::Regex;
 use reqwest::{header::HeaderValue, IntoUrl};
-use serde::{de::DeserializeOwned, Deserialize};
+use serde::de::DeserializeOwned;
 
+use crate::retry::RetryPolicy;
+
+pub mod assignments;
+pub mod courses;
+pub mod discussions;
+mod error;
 pub mod files;
 pub mod modules;
+pub mod pages;
+pub mod rate_limit;
+
+pub use error::{ApiError, ApiErrorDetail, Error, HttpError};
+use rate_limit::{RateLimitConfig, RateLimiter};
 
 pub struct Client {
     reqwest: reqwest::Client,
     host: String,
     auth_bearer: String,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ApiError {
-    pub errors: Vec<ApiErrorDetail>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct ApiErrorDetail {
-    pub message: String,
+    retry: RetryPolicy,
+    rate_limit: RateLimiter,
 }
 
 pub struct ResponsePagination {
@@ -59,12 +62,6 @@ impl From<&HeaderValue> for ResponsePagination {
     }
 }
 
-#[derive(Debug)]
-pub enum Error {
-    ApiError(ApiError),
-    ReqwestError(reqwest::Error),
-}
-
 pub type ApiResult<T> = Result<T, Error>;
 
 impl Client {
@@ -73,8 +70,18 @@ impl Client {
             reqwest: reqwest::Client::new(),
             host,
             auth_bearer,
+            retry: RetryPolicy::default(),
+            rate_limit: RateLimiter::new(RateLimitConfig::default()),
         }
     }
+    pub fn with_rate_limit(mut self, config: RateLimitConfig) -> Self {
+        self.rate_limit = RateLimiter::new(config);
+        self
+    }
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
     pub fn build_url(&self, path: &str) -> String {
         format!(
             "{}/{}",
@@ -86,6 +93,20 @@ impl Client {
         &self,
         url: U,
     ) -> ApiResult<(T, Option<ResponsePagination>)> {
+        let url = url.into_url().map_err(Error::ReqwestError)?;
+        self.retry
+            .run(&format!("Request to {}", url), |_| {
+                self.try_json_request(url.clone())
+            })
+            .await
+    }
+    async fn try_json_request<T: DeserializeOwned>(
+        &self,
+        url: reqwest::Url,
+    ) -> ApiResult<(T, Option<ResponsePagination>)> {
+        self.rate_limit.wait().await;
+
+        let url_str = url.to_string();
         let response = self
             .reqwest
             .get(url)
@@ -94,12 +115,19 @@ impl Client {
             .await
             .map_err(Error::ReqwestError)?;
 
-        if response.status() != 200 {
-            let json = response.json().await.map_err(Error::ReqwestError)?;
-            return Err(Error::ApiError(json));
-        }
+        self.rate_limit.update(response.headers());
+
+        let status = response.status();
         let pagination = response.headers().get("Link").map(|v| v.into());
-        let json = response.json().await.map_err(Error::ReqwestError)?;
+        let body = response.text().await.map_err(Error::ReqwestError)?;
+        if !status.is_success() {
+            return Err(Error::from_response(status, url_str, &body));
+        }
+        let json = serde_json::from_str(&body).map_err(|source| Error::DecodeError {
+            url: url_str,
+            body: error::snippet(&body),
+            source,
+        })?;
         Ok((json, pagination))
     }
 }
@@ -0,0 +1,26 @@
+use super::{Client, Error};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PageResp {
+    pub page_id: i64,
+    pub url: String,
+    pub title: String,
+    pub created_at: String,
+    pub updated_at: String,
+    pub body: Option<String>,
+    pub published: bool,
+    pub front_page: bool,
+    pub locked_for_user: bool,
+    pub lock_explanation: Option<String>,
+}
+
+impl Client {
+    pub async fn get_course_page(&self, courseid: i64, url: &str) -> Result<PageResp, Error> {
+        let url = self.build_url(&format!("/api/v1/courses/{}/pages/{}", courseid, url));
+
+        let (data, _) = self.make_json_request::<PageResp, _>(url).await?;
+
+        Ok(data)
+    }
+}
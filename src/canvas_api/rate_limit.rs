@@ -0,0 +1,80 @@
+use std::{sync::Mutex, time::Duration};
+
+use log::{debug, info};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+pub const REMAINING_HEADER: &str = "X-Rate-Limit-Remaining";
+pub const COST_HEADER: &str = "X-Request-Cost";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Start pausing before requests once the remaining quota drops below this.
+    pub low_watermark: f64,
+    /// How fast Canvas refills the quota, in units per second.
+    pub refill_per_sec: f64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            low_watermark: 150.0,
+            refill_per_sec: 10.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    remaining: Mutex<Option<f64>>,
+}
+
+fn header_f64(headers: &HeaderMap, name: &str) -> Option<f64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            remaining: Mutex::new(None),
+        }
+    }
+    pub fn remaining(&self) -> Option<f64> {
+        *self.remaining.lock().unwrap()
+    }
+    pub fn delay(&self) -> Option<Duration> {
+        let remaining = self.remaining()?;
+        if remaining >= self.config.low_watermark || self.config.refill_per_sec <= 0.0 {
+            return None;
+        }
+        let deficit = self.config.low_watermark - remaining;
+        Some(Duration::from_secs_f64(
+            deficit / self.config.refill_per_sec,
+        ))
+    }
+    pub async fn wait(&self) {
+        if let Some(delay) = self.delay() {
+            info!(
+                "Rate limit quota low ({:.1} remaining), pausing for {:?}",
+                self.remaining().unwrap_or_default(),
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            // Assume the quota refilled while we were waiting, the next response corrects it.
+            *self.remaining.lock().unwrap() = Some(self.config.low_watermark);
+        }
+    }
+    pub fn update(&self, headers: &HeaderMap) {
+        if let Some(remaining) = header_f64(headers, REMAINING_HEADER) {
+            debug!(
+                "Rate limit remaining: {}, request cost: {:?}",
+                remaining,
+                header_f64(headers, COST_HEADER)
+            );
+            *self.remaining.lock().unwrap() = Some(remaining);
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    canvas_api::{courses::CourseResp, Client, Error},
+    SanitizePolicy,
+};
+
+/// Case-insensitive substring filters on course names and term names.
+///
+/// A course is kept if it matches any include pattern (or there are none)
+/// and matches no exclude pattern.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CourseFilter {
+    pub include_terms: Vec<String>,
+    pub exclude_terms: Vec<String>,
+    pub include_names: Vec<String>,
+    pub exclude_names: Vec<String>,
+}
+
+fn matches_any(patterns: &[String], value: &str) -> bool {
+    let value = value.to_lowercase();
+    patterns
+        .iter()
+        .any(|pattern| value.contains(&pattern.to_lowercase()))
+}
+
+impl CourseFilter {
+    pub fn matches(&self, course: &CourseResp) -> bool {
+        let term = course.term.as_ref().map(|t| t.name.as_str()).unwrap_or("");
+        let name = course.name.as_deref().unwrap_or("");
+        let code = course.course_code.as_deref().unwrap_or("");
+
+        if !self.include_terms.is_empty() && !matches_any(&self.include_terms, term) {
+            return false;
+        }
+        if matches_any(&self.exclude_terms, term) {
+            return false;
+        }
+        if !self.include_names.is_empty()
+            && !matches_any(&self.include_names, name)
+            && !matches_any(&self.include_names, code)
+        {
+            return false;
+        }
+        !(matches_any(&self.exclude_names, name) || matches_any(&self.exclude_names, code))
+    }
+}
+
+/// Folder name for a course, e.g. `CS 101 (Fall 2023)`.
+pub fn course_folder_name(course: &CourseResp, policy: SanitizePolicy) -> String {
+    let code = course
+        .course_code
+        .clone()
+        .or(course.name.clone())
+        .unwrap_or_else(|| course.id.to_string());
+    let name = match &course.term {
+        Some(term) => format!("{} ({})", code, term.name),
+        None => code,
+    };
+    policy.sanitize(&name)
+}
+
+/// Lists the active courses of the current user that pass `filter`.
+pub async fn discover_courses(
+    client: &Client,
+    filter: &CourseFilter,
+) -> Result<Vec<CourseResp>, Error> {
+    let mut courses = Vec::new();
+    let mut stream = Box::pin(client.list_courses());
+    while let Some(course) = stream.next().await {
+        let course = course?;
+        if course.access_restricted_by_date.unwrap_or(false) {
+            continue;
+        }
+        if filter.matches(&course) {
+            courses.push(course);
+        }
+    }
+    Ok(courses)
+}
@@ -19,6 +19,6 @@ where
 #[macro_export]
 macro_rules! defer {
     ($code:block) => {
-        let _defer = crate::defer::Defer { f: Some(|| $code) };
+        let _defer = $crate::defer::Defer { f: Some(|| $code) };
     };
 }
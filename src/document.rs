@@ -0,0 +1,217 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::canvas_api::discussions::{
+    DiscussionEntryResp, DiscussionTopicResp, DiscussionViewResp,
+};
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn with_extension(base: &Path, ext: &str) -> PathBuf {
+    let mut name = base.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(ext);
+    base.with_file_name(name)
+}
+
+/// Renders `(label, value)` pairs as an HTML list to put in front of a document body.
+pub fn metadata_html(entries: &[(&str, String)]) -> String {
+    let mut html = String::from("<ul>\n");
+    for (label, value) in entries {
+        html.push_str(&format!(
+            "<li><strong>{}:</strong> {}</li>\n",
+            escape_html(label),
+            escape_html(value)
+        ));
+    }
+    html.push_str("</ul>\n");
+    html
+}
+
+static FILE_LINK_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"(?P<attr>href|src)=(?P<open>["'])(?P<url>[^"']*?/courses/(?P<course>\d+)/files/(?P<file>\d+)[^"']*)(?P<close>["'])"#,
+    )
+    .unwrap()
+});
+
+/// The id of the file of course `courseid` a `FILE_LINK_RE` match links to.
+fn linked_file_id(cap: &regex::Captures, courseid: i64) -> Option<i64> {
+    if cap["open"] != cap["close"] || cap["course"].parse() != Ok(courseid) {
+        return None;
+    }
+    cap["file"].parse().ok()
+}
+
+/// Finds the ids of files of the given course linked or embedded in an HTML body,
+/// in order of appearance.
+pub fn linked_file_ids(html: &str, courseid: i64) -> Vec<i64> {
+    let mut ids = Vec::new();
+    for cap in FILE_LINK_RE.captures_iter(html) {
+        if let Some(id) = linked_file_id(&cap, courseid) {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    ids
+}
+
+fn encode_href(name: &str) -> String {
+    name.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('#', "%23")
+        .replace('?', "%3F")
+        .replace('"', "%22")
+}
+
+/// Points links to course files at the local file names in `local`, leaving other links alone.
+pub fn rewrite_file_links(html: &str, courseid: i64, local: &HashMap<i64, String>) -> String {
+    FILE_LINK_RE
+        .replace_all(html, |cap: &regex::Captures| {
+            match linked_file_id(cap, courseid).and_then(|id| local.get(&id)) {
+                Some(name) => format!("{}=\"{}\"", &cap["attr"], encode_href(name)),
+                None => cap[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+fn entry_html(view: &DiscussionViewResp, entry: &DiscussionEntryResp, html: &mut String) {
+    html.push_str("<blockquote>\n");
+    html.push_str(&format!(
+        "<p><strong>{}</strong> at {}</p>\n",
+        escape_html(view.author(entry.user_id).unwrap_or("Unknown")),
+        escape_html(&entry.created_at)
+    ));
+    if entry.deleted {
+        html.push_str("<p><em>This reply was deleted.</em></p>\n");
+    } else if let Some(message) = &entry.message {
+        html.push_str(message);
+        html.push('\n');
+    }
+    for reply in &entry.replies {
+        entry_html(view, reply, html);
+    }
+    html.push_str("</blockquote>\n");
+}
+
+/// Renders a discussion topic and its full reply tree as an HTML body.
+pub fn discussion_html(topic: &DiscussionTopicResp, view: &DiscussionViewResp) -> String {
+    let mut entries = Vec::new();
+    let author = topic
+        .author
+        .as_ref()
+        .and_then(|a| a.display_name.clone())
+        .or(topic.user_name.clone());
+    if let Some(author) = author {
+        entries.push(("Author", author));
+    }
+    if let Some(posted_at) = &topic.posted_at {
+        entries.push(("Posted", posted_at.clone()));
+    }
+    entries.push(("Link", topic.html_url.clone()));
+
+    let mut html = metadata_html(&entries);
+    if let Some(message) = &topic.message {
+        html.push_str(message);
+        html.push('\n');
+    }
+    if !view.view.is_empty() {
+        html.push_str("<h2>Replies</h2>\n");
+        for entry in &view.view {
+            entry_html(view, entry, &mut html);
+        }
+    }
+    html
+}
+
+pub fn html_document(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n{body}\n</body>\n</html>\n",
+        title = escape_html(title),
+        body = body
+    )
+}
+
+pub fn html_to_markdown(title: &str, body: &str) -> String {
+    let markdown = htmd::convert(body).unwrap_or_else(|_| body.to_string());
+    format!("# {}\n\n{}\n", title, markdown.trim())
+}
+
+/// Writes `<base>.html` and, if requested, `<base>.md`, returning the paths written.
+pub fn write_document(
+    base: &Path,
+    title: &str,
+    body: &str,
+    markdown: bool,
+) -> Result<Vec<PathBuf>, io::Error> {
+    if let Some(parent) = base.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut written = Vec::new();
+
+    let html_path = with_extension(base, "html");
+    std::fs::File::create(&html_path)?.write_all(html_document(title, body).as_bytes())?;
+    written.push(html_path);
+
+    if markdown {
+        let md_path = with_extension(base, "md");
+        std::fs::File::create(&md_path)?.write_all(html_to_markdown(title, body).as_bytes())?;
+        written.push(md_path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = r#"<p><a href="https://canvas.example/courses/1/files/10/download">notes</a>
+<img src='/courses/1/files/11/preview'>
+<a href="/courses/2/files/12">other course</a>
+<a href="/courses/1/files/10?wrap=1">notes again</a>
+<a href="/courses/1/pages/intro">page</a></p>"#;
+
+    #[test]
+    fn finds_linked_files_of_the_course_once() {
+        assert_eq!(linked_file_ids(BODY, 1), [10, 11]);
+        assert_eq!(linked_file_ids(BODY, 2), [12]);
+        assert!(linked_file_ids(BODY, 3).is_empty());
+    }
+
+    #[test]
+    fn ignores_mismatched_quotes() {
+        assert!(linked_file_ids(r#"<a href="/courses/1/files/10'>x</a>"#, 1).is_empty());
+    }
+
+    #[test]
+    fn rewrites_links_to_local_files() {
+        let local = HashMap::from([
+            (10, "notes #1.pdf".to_string()),
+            (11, "100% \"final\"?.png".to_string()),
+            (12, "other.pdf".to_string()),
+        ]);
+        let html = rewrite_file_links(BODY, 1, &local);
+        assert_eq!(html.matches(r#"href="notes%20%231.pdf""#).count(), 2);
+        assert!(html.contains(r#"src="100%25%20%22final%22%3F.png""#));
+        assert!(html.contains(r#"href="/courses/2/files/12""#));
+        assert!(html.contains(r#"href="/courses/1/pages/intro""#));
+    }
+
+    #[test]
+    fn leaves_links_to_files_not_downloaded() {
+        assert_eq!(rewrite_file_links(BODY, 1, &HashMap::new()), BODY);
+    }
+}
@@ -1,9 +1,21 @@
-use crate::defer;
+use crate::{
+    defer,
+    path::link_or_copy,
+    report::SyncReport,
+    retry::{RetryPolicy, Retryable},
+    state::{FileRecord, SyncState},
+};
 use crossbeam_channel::bounded;
-use log::error;
+use log::{error, info};
+use reqwest::{
+    header::{ACCEPT_RANGES, RANGE},
+    StatusCode,
+};
 use std::{
+    fmt::Display,
+    fs::OpenOptions,
     io::Write,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 use tokio::task::JoinSet;
@@ -12,12 +24,16 @@ pub struct Downloader {
     task_channel: Option<crossbeam::channel::Sender<DownloadTask>>,
     joinset: JoinSet<()>,
     progress: Arc<Vec<Mutex<Option<DownloadProgress>>>>,
+    report: Arc<Mutex<SyncReport>>,
+    /// States the downloads are recorded in, saved once they are all done.
+    states: Mutex<Vec<Arc<SyncState>>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct DownloadProgress {
     pub total: usize,
     pub downloaded: usize,
+    pub attempt: u32,
     pub task: DownloadTask,
 }
 
@@ -25,51 +41,230 @@ pub struct DownloadProgress {
 pub struct DownloadTask {
     pub url: String,
     pub path: PathBuf,
+    pub size: Option<u64>,
+    /// Version of the remote file, partial downloads of other versions are not resumed.
+    pub version: Option<String>,
+    pub update: bool,
+    pub links: Vec<PathBuf>,
+    pub state: Option<(Arc<SyncState>, FileRecord)>,
+}
+
+impl DownloadTask {
+    pub fn with_state(mut self, state: &Arc<SyncState>, record: FileRecord) -> Self {
+        self.state = Some((state.clone(), record));
+        self
+    }
 }
 
 #[derive(Debug)]
 pub enum DownloadError {
     Io(std::io::Error),
     Reqwest(reqwest::Error),
+    Status(StatusCode),
+    /// The body ended before `Content-Length`, retrying can pick up the rest.
+    SizeMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The whole body arrived but does not have the size Canvas lists for the file.
+    WrongSize {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+pub fn partial_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+/// Where the version of the file a partial download belongs to is kept.
+fn partial_version_path(partial: &Path) -> PathBuf {
+    let mut name = partial.file_name().unwrap_or_default().to_os_string();
+    name.push(".version");
+    partial.with_file_name(name)
+}
+
+fn partial_matches(partial: &Path, version: Option<&str>) -> bool {
+    match version {
+        Some(version) => std::fs::read_to_string(partial_version_path(partial))
+            .map(|v| v == version)
+            .unwrap_or(false),
+        None => false,
+    }
+}
+
+fn remove_partial(partial: &Path) {
+    std::fs::remove_file(partial).ok();
+    std::fs::remove_file(partial_version_path(partial)).ok();
+}
+
+fn accepts_ranges(resp: &reqwest::Response) -> bool {
+    resp.headers()
+        .get(ACCEPT_RANGES)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("bytes"))
+        .unwrap_or(false)
+}
+
+async fn write_body(
+    resp: &mut reqwest::Response,
+    file: &mut std::fs::File,
+    progress: &Mutex<Option<DownloadProgress>>,
+) -> Result<(), DownloadError> {
+    while let Some(chunk) = resp.chunk().await.map_err(DownloadError::Reqwest)? {
+        progress.lock().unwrap().as_mut().unwrap().downloaded += chunk.len();
+        file.write_all(&chunk).map_err(DownloadError::Io)?;
+    }
+    Ok(())
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DownloadError::Io(e) => write!(f, "I/O error: {}", e),
+            DownloadError::Reqwest(e) => write!(f, "request failed: {}", e),
+            DownloadError::Status(status) => write!(f, "unexpected response: {}", status),
+            DownloadError::SizeMismatch { expected, actual } => write!(
+                f,
+                "size mismatch: expected {} bytes, got {}",
+                expected, actual
+            ),
+            DownloadError::WrongSize { expected, actual } => write!(
+                f,
+                "file is {} bytes, but Canvas lists it as {}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+impl Retryable for DownloadError {
+    fn is_retryable(&self, policy: &RetryPolicy) -> bool {
+        match self {
+            DownloadError::Io(_) => false,
+            DownloadError::Reqwest(e) => e.is_retryable(policy),
+            DownloadError::Status(status) => policy.is_retryable_status(*status),
+            DownloadError::SizeMismatch { .. } => true,
+            DownloadError::WrongSize { .. } => false,
+        }
+    }
 }
 
 pub async fn download_file(
     client: reqwest::Client,
-    url: &str,
-    path: &PathBuf,
+    task: &DownloadTask,
+    attempt: u32,
     progress: &Mutex<Option<DownloadProgress>>,
 ) -> Result<(), DownloadError> {
+    let DownloadTask {
+        url,
+        path,
+        size,
+        version,
+        ..
+    } = task;
+    let size = *size;
     defer!({
         progress.lock().unwrap().take();
     });
 
-    let mut resp = client
-        .get(url)
-        .send()
-        .await
-        .map_err(DownloadError::Reqwest)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(DownloadError::Io)?;
+    }
+
+    let partial = partial_path(path);
+    let mut offset = partial.metadata().map(|m| m.len()).unwrap_or(0);
+    if offset > 0 && !partial_matches(&partial, version.as_deref()) {
+        // Left over from another version of the file, its bytes cannot be reused.
+        info!("Discarding stale partial download of {:?}", path);
+        remove_partial(&partial);
+        offset = 0;
+    }
+
+    let mut req = client.get(url);
+    if offset > 0 {
+        req = req.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut resp = req.send().await.map_err(DownloadError::Reqwest)?;
+
+    if offset > 0 && resp.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+        // The partial file does not fit the remote file anymore, start over.
+        remove_partial(&partial);
+        offset = 0;
+        resp = client
+            .get(url)
+            .send()
+            .await
+            .map_err(DownloadError::Reqwest)?;
+    }
+    if !resp.status().is_success() {
+        return Err(DownloadError::Status(resp.status()));
+    }
+
+    let resuming = offset > 0 && resp.status() == StatusCode::PARTIAL_CONTENT;
+    let resumable = resuming || accepts_ranges(&resp);
+    if !resuming {
+        offset = 0;
+    } else {
+        info!("Resuming download of {:?} at byte {}", path, offset);
+    }
 
+    let content_length = resp.content_length().map(|len| offset + len);
     progress.lock().unwrap().replace(DownloadProgress {
-        total: resp.content_length().unwrap_or(0) as usize,
-        downloaded: 0,
-        task: DownloadTask {
-            url: url.to_string(),
-            path: path.clone(),
-        },
+        total: content_length.unwrap_or(0) as usize,
+        downloaded: offset as usize,
+        attempt,
+        task: task.clone(),
     });
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).map_err(DownloadError::Io)?;
+
+    let mut file = if resuming {
+        OpenOptions::new().append(true).open(&partial)
+    } else {
+        match version {
+            Some(version) => std::fs::write(partial_version_path(&partial), version),
+            None => Ok(()),
+        }
+        .and_then(|_| std::fs::File::create(&partial))
     }
-    let mut file = std::fs::File::create(&path).map_err(DownloadError::Io)?;
-    while let Some(chunk) = resp.chunk().await.map_err(DownloadError::Reqwest)? {
-        progress.lock().unwrap().as_mut().unwrap().downloaded += chunk.len();
-        file.write_all(&chunk).map_err(DownloadError::Io)?;
+    .map_err(DownloadError::Io)?;
+
+    if let Err(e) = write_body(&mut resp, &mut file, progress).await {
+        if !resumable {
+            remove_partial(&partial);
+        }
+        return Err(e);
+    }
+    let actual = file.metadata().map_err(DownloadError::Io)?.len();
+    drop(file);
+
+    if let Some(expected) = content_length {
+        if actual != expected {
+            return Err(DownloadError::SizeMismatch { expected, actual });
+        }
+    }
+    if let Some(expected) = size {
+        if actual != expected {
+            // The body was complete but does not match the file, resuming will not help.
+            remove_partial(&partial);
+            return Err(DownloadError::WrongSize { expected, actual });
+        }
     }
+
+    std::fs::rename(&partial, path).map_err(DownloadError::Io)?;
+    std::fs::remove_file(partial_version_path(&partial)).ok();
     Ok(())
 }
 
 impl Downloader {
     pub fn new(client: reqwest::Client, nprocs: usize) -> Self {
+        Self::with_retry_policy(client, nprocs, RetryPolicy::default())
+    }
+    pub fn with_retry_policy(client: reqwest::Client, nprocs: usize, retry: RetryPolicy) -> Self {
         let (tx, rx) = bounded::<DownloadTask>(100);
         let reqwest = client.clone();
         let mut js = JoinSet::new();
@@ -79,18 +274,62 @@ impl Downloader {
             progress.push(Mutex::new(None));
         }
         let progress = Arc::new(progress);
+        let report = Arc::new(Mutex::new(SyncReport::default()));
 
         for id in 0..nprocs {
             let rx = rx.clone();
             let reqwest = reqwest.clone();
             let progress = progress.clone();
+            let retry = retry.clone();
+            let report = report.clone();
             js.spawn(async move {
                 for task in rx {
-                    match download_file(reqwest.clone(), &task.url, &task.path, &progress[id]).await
+                    let what = format!("Download of {:?}", task.path);
+                    match retry
+                        .run(&what, |attempt| {
+                            download_file(reqwest.clone(), &task, attempt, &progress[id])
+                        })
+                        .await
                     {
-                        Ok(_) => {}
+                        Ok(_) => {
+                            let mut linked = Vec::new();
+                            for link in task.links {
+                                // The download replaced the file, so any old link is stale.
+                                std::fs::remove_file(&link).ok();
+                                match link_or_copy(&task.path, &link) {
+                                    Ok(_) => linked.push(link),
+                                    Err(e) => {
+                                        error!(
+                                            "Failed to link {:?} to {:?}: {}",
+                                            link, task.path, e
+                                        );
+                                        report.lock().unwrap().fail(
+                                            format!("linking {:?} to {:?}", link, task.path),
+                                            e,
+                                        );
+                                    }
+                                }
+                            }
+                            let mut report = report.lock().unwrap();
+                            for link in &linked {
+                                report.linked.push((task.path.clone(), link.clone()));
+                            }
+                            if let Some((state, mut record)) = task.state {
+                                record.links = linked;
+                                state.record(record);
+                            }
+                            if task.update {
+                                report.updated.push(task.path);
+                            } else {
+                                report.downloaded.push(task.path);
+                            }
+                        }
                         Err(e) => {
-                            error!("Failed to download file: {:?}", e);
+                            error!("Failed to download {:?}: {}", task.path, e);
+                            report
+                                .lock()
+                                .unwrap()
+                                .fail(format!("downloading {:?}", task.path), e);
                         }
                     }
                 }
@@ -100,20 +339,42 @@ impl Downloader {
             task_channel: Some(tx),
             joinset: js,
             progress,
+            report,
+            states: Mutex::new(Vec::new()),
         }
     }
     pub fn progress(&self) -> Arc<Vec<Mutex<Option<DownloadProgress>>>> {
         self.progress.clone()
     }
     pub fn submit(&self, task: DownloadTask) {
+        if let Some((state, _)) = &task.state {
+            let mut states = self.states.lock().unwrap();
+            if !states.iter().any(|s| Arc::ptr_eq(s, state)) {
+                states.push(state.clone());
+            }
+        }
         self.task_channel
             .as_ref()
             .expect("attempt to submit task to closed downloader")
             .send(task)
             .unwrap();
     }
-    pub async fn finish(&mut self) {
+    pub async fn finish(&mut self) -> SyncReport {
         self.task_channel = None;
-        while let Some(_) = self.joinset.join_next().await {}
+        while self.joinset.join_next().await.is_some() {}
+        let mut report = std::mem::take(&mut *self.report.lock().unwrap());
+        for state in std::mem::take(&mut *self.states.lock().unwrap()) {
+            if let Err(e) = state.save() {
+                error!("Failed to save sync state: {}", e);
+                report.fail(
+                    format!(
+                        "saving sync state {:?}",
+                        SyncState::state_path(state.root())
+                    ),
+                    e,
+                );
+            }
+        }
+        report
     }
 }
@@ -0,0 +1,415 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    canvas_api::{
+        courses::CourseResp,
+        modules::{ModuleItemResp, ModuleResp},
+        Client, Error,
+    },
+    SanitizePolicy,
+};
+
+pub const DEFAULT_MODULES_LAYOUT: &str = "Modules/{module}/{subheader}/{name}";
+pub const DEFAULT_FILES_LAYOUT: &str = "{folder}/{name}";
+
+const COURSE_PLACEHOLDERS: &[&str] = &["term", "course_code", "course_name"];
+/// Placeholders both templates can use, besides the course ones.
+const ITEM_PLACEHOLDERS: &[&str] = &[
+    "course_id",
+    "item_type",
+    "content_type",
+    "name",
+    "stem",
+    "ext",
+];
+const MODULE_PLACEHOLDERS: &[&str] = &["module", "module_position", "subheader", "item_position"];
+const FILE_PLACEHOLDERS: &[&str] = &["folder"];
+
+static PLACEHOLDER_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{([a-z_]+)(?::0*(\d+))?\}").unwrap());
+/// Anything that looks like a placeholder, to catch misspelled ones.
+static BRACES_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r"\{[^{}/]*\}").unwrap());
+
+/// Path templates, relative to the course folder, for synced items.
+///
+/// `{name}` style placeholders are replaced with the item's values, and
+/// numbers can be zero padded with a width like `{module_position:02}`. A path
+/// segment made of only `{subheader}` or `{folder}` expands to one folder per
+/// level, and segments that end up empty are dropped.
+///
+/// Placeholders: `{course_id}`, `{course_name}`, `{course_code}`, `{term}`,
+/// `{module}`, `{module_position}`, `{subheader}`, `{item_position}`,
+/// `{item_type}`, `{folder}`, `{name}`, `{stem}`, `{ext}` (with the dot, so
+/// `{stem}{ext}` is the name) and `{content_type}`.
+///
+/// Names are cleaned up following the `sanitize` policy.
+///
+/// With `numbered`, module folders and module items get their Canvas position
+/// as a zero padded prefix, like `01 - Intro`, so they sort in course order.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Layout {
+    pub modules: String,
+    pub files: String,
+    pub numbered: bool,
+    pub number_width: usize,
+    pub sanitize: SanitizePolicy,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            modules: DEFAULT_MODULES_LAYOUT.to_string(),
+            files: DEFAULT_FILES_LAYOUT.to_string(),
+            numbered: false,
+            number_width: 2,
+            sanitize: SanitizePolicy::default(),
+        }
+    }
+}
+
+impl Layout {
+    /// Checks that the templates only use placeholders they have values for.
+    pub fn validate(&self) -> Result<(), String> {
+        check_template("modules", &self.modules, MODULE_PLACEHOLDERS)?;
+        check_template("files", &self.files, FILE_PLACEHOLDERS)
+    }
+    fn uses_course(&self) -> bool {
+        [&self.modules, &self.files].iter().any(|template| {
+            PLACEHOLDER_RE
+                .captures_iter(template)
+                .any(|cap| COURSE_PLACEHOLDERS.contains(&&cap[1]))
+        })
+    }
+}
+
+fn check_template(which: &str, template: &str, specific: &[&str]) -> Result<(), String> {
+    let known = |placeholder: &str| {
+        PLACEHOLDER_RE
+            .captures(placeholder)
+            .filter(|cap| cap[0].len() == placeholder.len())
+            .is_some_and(|cap| {
+                [COURSE_PLACEHOLDERS, ITEM_PLACEHOLDERS, specific]
+                    .iter()
+                    .any(|names| names.contains(&&cap[1]))
+            })
+    };
+    let unknown = BRACES_RE
+        .find_iter(template)
+        .map(|m| m.as_str())
+        .filter(|placeholder| !known(placeholder))
+        .collect::<Vec<_>>();
+    if unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "unknown placeholder {} in {} layout {:?}",
+            unknown.join(", "),
+            which,
+            template
+        ))
+    }
+}
+
+/// Placeholder values, each one or more path segments.
+#[derive(Debug, Clone, Default)]
+pub struct LayoutVars {
+    vars: Vec<(&'static str, Vec<String>)>,
+}
+
+impl LayoutVars {
+    pub fn set(&mut self, name: &'static str, value: impl Into<String>) -> &mut Self {
+        self.set_all(name, vec![value.into()])
+    }
+    pub fn set_all(&mut self, name: &'static str, values: Vec<String>) -> &mut Self {
+        self.vars.retain(|(n, _)| *n != name);
+        self.vars.push((name, values));
+        self
+    }
+    fn get(&self, name: &str) -> Option<&Vec<String>> {
+        self.vars.iter().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
+    /// Sets `{name}` along with its `{stem}` and `{ext}`.
+    fn set_name(&mut self, name: &str) -> &mut Self {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+            _ => (name, String::new()),
+        };
+        self.set("name", name).set("stem", stem).set("ext", ext)
+    }
+}
+
+fn pad(value: &str, width: Option<usize>) -> String {
+    match width {
+        Some(width) if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+            format!("{:0>width$}", value, width = width)
+        }
+        _ => value.to_string(),
+    }
+}
+
+fn lookup(vars: &LayoutVars, cap: &regex::Captures) -> Option<Vec<String>> {
+    let width = cap.get(2).and_then(|w| w.as_str().parse().ok());
+    vars.get(&cap[1])
+        .map(|values| values.iter().map(|v| pad(v, width)).collect())
+}
+
+/// Renders a template into sanitized path segments.
+pub fn render(template: &str, vars: &LayoutVars, policy: SanitizePolicy) -> Vec<String> {
+    let mut segments = Vec::new();
+    for part in template.split('/') {
+        let whole = PLACEHOLDER_RE
+            .captures(part)
+            .filter(|cap| cap[0].len() == part.len())
+            .and_then(|cap| lookup(vars, &cap));
+        if let Some(values) = whole {
+            segments.extend(
+                values
+                    .iter()
+                    .map(|v| policy.sanitize(v.trim()))
+                    .filter(|v| !v.is_empty()),
+            );
+            continue;
+        }
+        let rendered =
+            PLACEHOLDER_RE.replace_all(part, |cap: &regex::Captures| match lookup(vars, cap) {
+                Some(values) => values.join(" - "),
+                None => cap[0].to_string(),
+            });
+        let rendered = policy.sanitize(rendered.trim());
+        if !rendered.is_empty() {
+            segments.push(rendered);
+        }
+    }
+    segments
+}
+
+pub fn segments_path(root: &Path, segments: &[String]) -> PathBuf {
+    let mut path = root.to_path_buf();
+    path.extend(segments);
+    path
+}
+
+/// A layout bound to one course.
+#[derive(Debug, Clone)]
+pub struct CourseLayout {
+    layout: Layout,
+    course: LayoutVars,
+}
+
+impl CourseLayout {
+    pub fn new(layout: Layout, courseid: i64, course: Option<&CourseResp>) -> Self {
+        let mut vars = LayoutVars::default();
+        vars.set("course_id", courseid.to_string());
+        if let Some(course) = course {
+            vars.set("course_name", course.name.clone().unwrap_or_default())
+                .set(
+                    "course_code",
+                    course.course_code.clone().unwrap_or_default(),
+                )
+                .set(
+                    "term",
+                    course
+                        .term
+                        .as_ref()
+                        .map(|t| t.name.clone())
+                        .unwrap_or_default(),
+                );
+        }
+        Self {
+            layout,
+            course: vars,
+        }
+    }
+
+    /// Looks up the course only if the templates need it.
+    pub async fn fetch(layout: &Layout, courseid: i64, client: &Client) -> Result<Self, Error> {
+        let course = if layout.uses_course() {
+            Some(client.get_course(courseid).await?)
+        } else {
+            None
+        };
+        Ok(Self::new(layout.clone(), courseid, course.as_ref()))
+    }
+
+    pub fn sanitize(&self) -> SanitizePolicy {
+        self.layout.sanitize
+    }
+
+    /// Path of a module item named `name`, `content_type` being the MIME type of files.
+    pub fn module_item(
+        &self,
+        module: &ModuleResp,
+        headers: &[String],
+        item: &ModuleItemResp,
+        name: &str,
+        content_type: &str,
+    ) -> Vec<String> {
+        let (module_name, name) = if self.layout.numbered {
+            let width = Some(self.layout.number_width);
+            (
+                format!(
+                    "{} - {}",
+                    pad(&module.position.to_string(), width),
+                    module.name
+                ),
+                format!("{} - {}", pad(&item.position.to_string(), width), name),
+            )
+        } else {
+            (module.name.clone(), name.to_string())
+        };
+        let mut vars = self.course.clone();
+        vars.set("module", module_name)
+            .set("module_position", module.position.to_string())
+            .set_all("subheader", headers.to_vec())
+            .set("item_position", item.position.to_string())
+            .set("item_type", item.type_.clone())
+            .set("content_type", content_type)
+            .set_name(&name);
+        render(&self.layout.modules, &vars, self.layout.sanitize)
+    }
+
+    pub fn file(&self, folder: &[String], name: &str, content_type: &str) -> Vec<String> {
+        let mut vars = self.course.clone();
+        vars.set_all("folder", folder.to_vec())
+            .set("item_type", "File")
+            .set("content_type", content_type)
+            .set_name(name);
+        render(&self.layout.files, &vars, self.layout.sanitize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> LayoutVars {
+        let mut vars = LayoutVars::default();
+        vars.set("module", "Week 1")
+            .set("module_position", "3")
+            .set_all("subheader", vec!["Slides".to_string(), "Extra".to_string()])
+            .set("item_type", "")
+            .set_name("intro.pdf");
+        vars
+    }
+
+    fn render_default(template: &str) -> Vec<String> {
+        render(template, &vars(), SanitizePolicy::default())
+    }
+
+    #[test]
+    fn whole_segment_expands_to_one_folder_per_value() {
+        assert_eq!(
+            render_default("Modules/{module}/{subheader}/{name}"),
+            ["Modules", "Week 1", "Slides", "Extra", "intro.pdf"]
+        );
+        // Inside a segment the values are joined instead.
+        assert_eq!(
+            render_default("{module} ({subheader})/{name}"),
+            ["Week 1 (Slides - Extra)", "intro.pdf"]
+        );
+    }
+
+    #[test]
+    fn empty_segments_are_dropped() {
+        assert_eq!(render_default("{item_type}/{name}"), ["intro.pdf"]);
+        assert_eq!(render_default("a//{item_type} /b"), ["a", "b"]);
+        let mut vars = vars();
+        vars.set_all("subheader", Vec::new());
+        assert_eq!(
+            render(
+                "{module}/{subheader}/{name}",
+                &vars,
+                SanitizePolicy::default()
+            ),
+            ["Week 1", "intro.pdf"]
+        );
+    }
+
+    #[test]
+    fn numbers_are_padded() {
+        assert_eq!(
+            render_default("{module_position:02} {stem}{ext}"),
+            ["03 intro.pdf"]
+        );
+        assert_eq!(pad("7", Some(3)), "007");
+        assert_eq!(pad("1234", Some(2)), "1234");
+        assert_eq!(pad("", Some(2)), "");
+        // Only plain numbers are padded.
+        assert_eq!(pad("Week 1", Some(10)), "Week 1");
+        assert_eq!(pad("-1", Some(3)), "-1");
+        assert_eq!(pad("7", None), "7");
+    }
+
+    #[test]
+    fn names_are_split_into_stem_and_ext() {
+        let split = |name: &str| {
+            let mut vars = LayoutVars::default();
+            vars.set_name(name);
+            (
+                vars.get("stem").unwrap()[0].clone(),
+                vars.get("ext").unwrap()[0].clone(),
+            )
+        };
+        assert_eq!(
+            split("intro.pdf"),
+            ("intro".to_string(), ".pdf".to_string())
+        );
+        assert_eq!(split("a.tar.gz"), ("a.tar".to_string(), ".gz".to_string()));
+        assert_eq!(split("README"), ("README".to_string(), String::new()));
+        assert_eq!(split(".bashrc"), (".bashrc".to_string(), String::new()));
+    }
+
+    #[test]
+    fn placeholder_values_are_sanitized() {
+        let mut vars = vars();
+        vars.set("module", "a/b: c?");
+        assert_eq!(
+            render("{module}", &vars, SanitizePolicy::Windows),
+            ["a_b_ c_"]
+        );
+        assert_eq!(
+            render("{module}", &vars, SanitizePolicy::Posix),
+            ["a_b: c?"]
+        );
+    }
+
+    #[test]
+    fn unknown_placeholders_are_rejected() {
+        assert_eq!(Layout::default().validate(), Ok(()));
+        let layout = |modules: &str, files: &str| Layout {
+            modules: modules.to_string(),
+            files: files.to_string(),
+            ..Layout::default()
+        };
+        assert_eq!(
+            layout(
+                "{term}/{module_position:02} {module}/{name}",
+                "{course_code}/{folder}/{stem}{ext}"
+            )
+            .validate(),
+            Ok(())
+        );
+        let error = layout("{modul}/{name}", DEFAULT_FILES_LAYOUT)
+            .validate()
+            .unwrap_err();
+        assert!(error.contains("{modul}"), "{}", error);
+        assert!(layout("{Module}/{name}", DEFAULT_FILES_LAYOUT)
+            .validate()
+            .is_err());
+        assert!(layout("{module:}/{name}", DEFAULT_FILES_LAYOUT)
+            .validate()
+            .is_err());
+        // Module placeholders have no value for files and the other way round.
+        assert!(layout(DEFAULT_MODULES_LAYOUT, "{module}/{name}")
+            .validate()
+            .is_err());
+        assert!(layout("{folder}/{name}", DEFAULT_FILES_LAYOUT)
+            .validate()
+            .is_err());
+    }
+}
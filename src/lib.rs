@@ -1,19 +1,35 @@
 use std::collections::HashMap;
 
-use canvas_api::files::{FileResp, FolderResp};
+use canvas_api::{
+    discussions::AttachmentResp,
+    files::{FileResp, FolderResp},
+};
 use download::DownloadTask;
-use path::sanitize_file_name;
+
+pub use path::SanitizePolicy;
 
 pub mod canvas_api;
+pub mod courses;
 mod defer;
+pub mod document;
 pub mod download;
+pub mod layout;
 mod path;
+pub mod report;
+pub mod retry;
+pub mod state;
+pub mod status;
 pub mod sync;
+pub mod tree;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct File {
+    pub id: i64,
+    pub root: std::path::PathBuf,
+    pub sanitize: SanitizePolicy,
     pub folder_path: Vec<String>,
     pub file_name: String,
+    pub content_type: String,
     pub size: i64,
     pub created_at: String,
     pub updated_at: String,
@@ -21,25 +37,59 @@ pub struct File {
     pub url: String,
 }
 
-impl Into<DownloadTask> for File {
-    fn into(self) -> DownloadTask {
+impl From<File> for DownloadTask {
+    fn from(file: File) -> Self {
         DownloadTask {
-            path: self.local_path(),
-            url: self.url,
+            path: file.local_path(),
+            size: Some(file.size as u64),
+            version: Some(file.updated_at),
+            url: file.url,
+            update: false,
+            links: Vec::new(),
+            state: None,
         }
     }
 }
 
+/// Names of the folders from the course root down to `folder_id`, `None` if one of them is
+/// not in `folder_map`.
+pub(crate) fn folder_names(
+    folder_map: &HashMap<i64, FolderResp>,
+    folder_id: i64,
+) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+    let mut cur_folder = folder_id;
+    while cur_folder != 0 {
+        let folder = folder_map.get(&cur_folder)?;
+        names.push(folder.name.clone());
+        cur_folder = folder.parent_folder_id.unwrap_or(0);
+    }
+    names.reverse();
+    Some(names)
+}
+
 impl File {
-    pub fn set_folder_path(&mut self, folder_map: &HashMap<i64, FolderResp>, folder_id: i64) {
-        self.folder_path.clear();
-        let mut cur_folder = folder_id;
-        while cur_folder != 0 {
-            let folder = folder_map.get(&cur_folder).unwrap();
-            self.folder_path.push(folder.name.clone());
-            cur_folder = folder.parent_folder_id.unwrap_or(0);
+    /// Places the file in folder `folder_id`, returning `false` if the folder or one of its
+    /// parents is not in `folder_map`.
+    pub fn set_folder_path(
+        &mut self,
+        folder_map: &HashMap<i64, FolderResp>,
+        folder_id: i64,
+    ) -> bool {
+        match folder_names(folder_map, folder_id) {
+            Some(names) => {
+                self.folder_path = names;
+                true
+            }
+            None => false,
         }
-        self.folder_path.reverse();
+    }
+    /// Places the file at `segments` under the root, the last segment being the file name.
+    pub fn set_path_segments(&mut self, mut segments: Vec<String>) {
+        if let Some(name) = segments.pop() {
+            self.file_name = name;
+        }
+        self.folder_path = segments;
     }
     pub fn local_file_matches(&self) -> Result<bool, std::io::Error> {
         let path = self.local_path();
@@ -53,7 +103,7 @@ impl File {
         Ok(true)
     }
     pub fn local_path(&self) -> std::path::PathBuf {
-        let mut path = std::path::PathBuf::new();
+        let mut path = self.root.clone();
         for folder in self.sanitized_folder_path() {
             path.push(folder);
         }
@@ -63,19 +113,23 @@ impl File {
     pub fn sanitized_folder_path(&self) -> Vec<String> {
         self.folder_path
             .iter()
-            .map(|s| sanitize_file_name(s))
+            .map(|s| self.sanitize.sanitize(s))
             .collect::<Vec<_>>()
     }
     pub fn sanitized_file_name(&self) -> String {
-        sanitize_file_name(&self.file_name)
+        self.sanitize.sanitize(&self.file_name)
     }
 }
 
 impl From<FileResp> for File {
     fn from(value: FileResp) -> Self {
         Self {
+            id: value.id,
+            root: std::path::PathBuf::new(),
+            sanitize: SanitizePolicy::default(),
             folder_path: Vec::new(),
             file_name: value.display_name,
+            content_type: value.content_type,
             size: value.size,
             created_at: value.created_at,
             updated_at: value.updated_at,
@@ -84,3 +138,23 @@ impl From<FileResp> for File {
         }
     }
 }
+
+impl From<AttachmentResp> for File {
+    fn from(value: AttachmentResp) -> Self {
+        Self {
+            id: value.id,
+            root: std::path::PathBuf::new(),
+            sanitize: SanitizePolicy::default(),
+            folder_path: Vec::new(),
+            file_name: value.display_name,
+            content_type: value.content_type,
+            size: value.size,
+            created_at: value.created_at,
+            modified_at: value
+                .modified_at
+                .unwrap_or_else(|| value.updated_at.clone()),
+            updated_at: value.updated_at,
+            url: value.url,
+        }
+    }
+}
@@ -1,19 +1,181 @@
 use std::{
+    fmt::Display,
     fs::File,
     io::{self, Write},
+    path::{Path, PathBuf},
 };
 
-pub fn sanitize_file_name(file_name: &str) -> String {
-    file_name
-        .replace("/", "_")
-        .replace("\\", "_")
-        .replace(":", "_")
-        .replace("*", "_")
-        .replace("?", "_")
-        .replace("\"", "_")
-        .replace("<", "_")
-        .replace(">", "_")
-        .replace("|", "_")
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+/// Bytes kept free in every name for the suffixes added when saving, like `.part` or `.html`.
+const SUFFIX_ROOM: usize = 16;
+/// In bytes, or UTF-16 units for `SanitizePolicy::Windows`.
+const MAX_NAME_LEN: usize = 255 - SUFFIX_ROOM;
+
+const WINDOWS_RESERVED_CHARS: &[char] = &['\\', ':', '*', '?', '"', '<', '>', '|'];
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Rules file and folder names are made to follow.
+///
+/// All policies normalize names to Unicode NFC, replace `/` and control characters with `_`,
+/// and shorten names to fit the file system's 255 limit, keeping room for the suffixes
+/// added when saving. `windows` also replaces the characters Windows does not allow,
+/// renames reserved device names like `CON` or `NUL.txt` and drops trailing dots and spaces,
+/// measuring length in UTF-16 units. `portable`, the default, applies the Windows rules and
+/// counts bytes, so names work everywhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SanitizePolicy {
+    #[default]
+    Portable,
+    Windows,
+    Posix,
+}
+
+impl SanitizePolicy {
+    fn windows_rules(self) -> bool {
+        self != SanitizePolicy::Posix
+    }
+    fn len(self, name: &str) -> usize {
+        match self {
+            SanitizePolicy::Windows => name.encode_utf16().count(),
+            _ => name.len(),
+        }
+    }
+    /// Turns `name` into a single valid path segment. Sanitizing twice changes nothing.
+    ///
+    /// An empty name stays empty, so layouts can leave out segments.
+    pub fn sanitize(self, name: &str) -> String {
+        match name {
+            "" => return String::new(),
+            "." | ".." => return "_".repeat(name.len()),
+            _ => {}
+        }
+        let mut name = name
+            .nfc()
+            .map(|c| {
+                if c == '/'
+                    || c.is_control()
+                    || (self.windows_rules() && WINDOWS_RESERVED_CHARS.contains(&c))
+                {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect::<String>();
+        if self.windows_rules() {
+            name.truncate(name.trim_end_matches(['.', ' ']).len());
+            let stem = name.split('.').next().unwrap_or_default();
+            if WINDOWS_RESERVED_NAMES
+                .iter()
+                .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+            {
+                name.insert(stem.len(), '_');
+            }
+        }
+        let name = self.shorten(name);
+        if name.is_empty() {
+            // Nothing was left after dropping trailing dots and spaces.
+            return "_".to_string();
+        }
+        name
+    }
+    /// Sanitizes `name` and adds ` (id)` to it, before its extension if `extension` is set.
+    /// The name is shortened before adding the id, so the id is never cut off.
+    pub fn sanitize_with_id(self, name: &str, id: impl Display, extension: bool) -> String {
+        let name = self.sanitize(name);
+        let (stem, ext) = if extension {
+            split_extension(&name)
+        } else {
+            (name.as_str(), "")
+        };
+        let suffix = format!(" ({})", id);
+        let stem = self.cut(stem, MAX_NAME_LEN - self.len(&suffix) - self.len(ext));
+        stem + &suffix + ext
+    }
+    /// Cuts `name` down to the length limit, keeping a short extension if it has one.
+    fn shorten(self, name: String) -> String {
+        if self.len(&name) <= MAX_NAME_LEN {
+            return name;
+        }
+        let (stem, ext) = split_extension(&name);
+        self.cut(stem, MAX_NAME_LEN - self.len(ext)) + ext
+    }
+    /// The longest start of `stem` that fits in `room`.
+    fn cut(self, stem: &str, mut room: usize) -> String {
+        let mut short = String::new();
+        for c in stem.chars() {
+            let len = self.len(c.encode_utf8(&mut [0; 4]));
+            if len > room {
+                break;
+            }
+            room -= len;
+            short.push(c);
+        }
+        // Shortening may leave a trailing dot or space behind.
+        if self.windows_rules() {
+            short.truncate(short.trim_end_matches(['.', ' ']).len());
+        }
+        short
+    }
+}
+
+/// Splits `name` into its stem and its extension, including the dot, if the extension is
+/// short enough to be kept when shortening.
+fn split_extension(name: &str) -> (&str, &str) {
+    match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot <= SUFFIX_ROOM => name.split_at(dot),
+        _ => (name, ""),
+    }
+}
+
+/// Makes `to` a hard link of `from`, falling back to a copy where hard links are not supported.
+///
+/// Returns `false` if `to` was already in place.
+pub(crate) fn link_or_copy(from: &Path, to: &Path) -> Result<bool, io::Error> {
+    if let (Ok(a), Ok(b)) = (from.metadata(), to.metadata()) {
+        if a.len() == b.len() {
+            return Ok(false);
+        }
+        std::fs::remove_file(to)?;
+    }
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::hard_link(from, to).is_err() {
+        std::fs::copy(from, to)?;
+    }
+    Ok(true)
+}
+
+/// Removes `dir` and its parents up to, but not including, `root` as long as they are empty.
+pub(crate) fn remove_empty_dirs(dir: &Path, root: &Path) {
+    let mut dir = Some(dir);
+    while let Some(current) = dir {
+        if current == root || !current.starts_with(root) || current.as_os_str().is_empty() {
+            break;
+        }
+        if std::fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// Path of the link file written for `base`.
+#[cfg(target_os = "windows")]
+pub(crate) fn url_file_path(base: &Path) -> PathBuf {
+    crate::document::with_extension(base, "url")
+}
+
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn url_file_path(base: &Path) -> PathBuf {
+    crate::document::with_extension(base, "desktop")
 }
 
 #[cfg(target_os = "windows")]
@@ -21,18 +183,24 @@ pub(crate) fn write_url_file(
     url: &str,
     _name: &str,
     file_name_base: &str,
-) -> Result<(), io::Error> {
+) -> Result<PathBuf, io::Error> {
     eprintln!("Writing url file: {}", file_name_base);
-    let mut file = File::create(format!("{}.url", file_name_base))?;
+    let path = url_file_path(Path::new(file_name_base));
+    let mut file = File::create(&path)?;
     writeln!(file, "[InternetShortcut]")?;
     writeln!(file, "URL={}", url)?;
 
-    Ok(())
+    Ok(path)
 }
 
 #[cfg(not(target_os = "windows"))]
-pub(crate) fn write_url_file(url: &str, name: &str, file_name_base: &str) -> Result<(), io::Error> {
-    let mut file = File::create(format!("{}.desktop", file_name_base))?;
+pub(crate) fn write_url_file(
+    url: &str,
+    _name: &str,
+    file_name_base: &str,
+) -> Result<PathBuf, io::Error> {
+    let path = url_file_path(Path::new(file_name_base));
+    let mut file = File::create(&path)?;
     writeln!(file, "[Desktop Entry]")?;
     writeln!(file, "Encoding=UTF-8")?;
     writeln!(file, "Name={}", file_name_base)?;
@@ -40,5 +208,135 @@ pub(crate) fn write_url_file(url: &str, name: &str, file_name_base: &str) -> Res
     writeln!(file, "URL={}", url)?;
     writeln!(file, "Icon=text-html")?;
 
-    Ok(())
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn id_survives_shortening() {
+        for policy in [
+            SanitizePolicy::Portable,
+            SanitizePolicy::Windows,
+            SanitizePolicy::Posix,
+        ] {
+            let name = format!("{}.pdf", "é".repeat(200));
+            let with_id = policy.sanitize_with_id(&name, 1234, true);
+            assert!(with_id.ends_with(" (1234).pdf"), "{}", with_id);
+            assert!(policy.len(&with_id) <= MAX_NAME_LEN);
+            assert_eq!(policy.sanitize(&with_id), with_id);
+        }
+    }
+
+    const POLICIES: [SanitizePolicy; 3] = [
+        SanitizePolicy::Portable,
+        SanitizePolicy::Windows,
+        SanitizePolicy::Posix,
+    ];
+
+    #[test]
+    fn empty_and_dot_names() {
+        for policy in POLICIES {
+            assert_eq!(policy.sanitize(""), "");
+            assert_eq!(policy.sanitize("."), "_");
+            assert_eq!(policy.sanitize(".."), "__");
+        }
+        assert_eq!(SanitizePolicy::Windows.sanitize("..."), "_");
+        assert_eq!(SanitizePolicy::Posix.sanitize("..."), "...");
+    }
+
+    #[test]
+    fn reserved_names() {
+        for policy in [SanitizePolicy::Portable, SanitizePolicy::Windows] {
+            assert_eq!(policy.sanitize("CON"), "CON_");
+            assert_eq!(policy.sanitize("nul.txt"), "nul_.txt");
+            assert_eq!(policy.sanitize("Com1.tar.gz"), "Com1_.tar.gz");
+            assert_eq!(policy.sanitize("LPT9 "), "LPT9_");
+            assert_eq!(policy.sanitize("CONSOLE.txt"), "CONSOLE.txt");
+            assert_eq!(policy.sanitize("a:b?.txt"), "a_b_.txt");
+        }
+        assert_eq!(SanitizePolicy::Posix.sanitize("CON"), "CON");
+        assert_eq!(SanitizePolicy::Posix.sanitize("a:b?.txt"), "a:b?.txt");
+    }
+
+    #[test]
+    fn trailing_dots_and_spaces() {
+        for policy in [SanitizePolicy::Portable, SanitizePolicy::Windows] {
+            assert_eq!(policy.sanitize("notes. . "), "notes");
+            assert_eq!(policy.sanitize(" v1.2."), " v1.2");
+        }
+        assert_eq!(SanitizePolicy::Posix.sanitize("notes. "), "notes. ");
+    }
+
+    #[test]
+    fn control_characters_and_slashes() {
+        for policy in POLICIES {
+            assert_eq!(policy.sanitize("a\0b\tc\nd\u{7f}e"), "a_b_c_d_e");
+            assert_eq!(policy.sanitize("a/b"), "a_b");
+        }
+    }
+
+    #[test]
+    fn nfd_input_is_composed() {
+        for policy in POLICIES {
+            assert_eq!(policy.sanitize("Re\u{301}sume\u{301}.pdf"), "Résumé.pdf");
+        }
+    }
+
+    #[test]
+    fn long_names_fit_the_limit() {
+        for policy in [SanitizePolicy::Portable, SanitizePolicy::Posix] {
+            let name = policy.sanitize(&format!("{}.pdf", "é".repeat(300)));
+            assert!(name.ends_with("é.pdf"), "{}", name);
+            assert!(name.len() + SUFFIX_ROOM <= 255);
+            // A long extension is not kept.
+            let name = policy.sanitize(&format!("a.{}", "b".repeat(300)));
+            assert_eq!(name.len(), MAX_NAME_LEN);
+        }
+        // Windows counts UTF-16 units instead of bytes.
+        let name = SanitizePolicy::Windows.sanitize(&"é".repeat(300));
+        assert_eq!(name.chars().count(), MAX_NAME_LEN);
+    }
+
+    /// Sanitizing any name gives a usable segment that sanitizing again leaves alone.
+    #[test]
+    fn sanitizing_is_idempotent() {
+        const CHARS: &[char] = &[
+            'a', 'B', 'c', 'O', 'n', 'N', 'u', 'l', 'L', 'P', 'T', '1', '.', ' ', '/', '\\', ':',
+            '*', '"', '\0', '\t', '\u{7f}', 'e', '\u{301}', '\u{308}', 'é', 'ß', '가', '\u{11a8}',
+            '😀',
+        ];
+        // A fixed xorshift sequence keeps failures reproducible.
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+        for _ in 0..5000 {
+            let len = match next() % 4 {
+                0 => next() % 4,
+                1 => next() % 16,
+                _ => next() % 400,
+            };
+            let name = (0..len)
+                .map(|_| CHARS[next() % CHARS.len()])
+                .collect::<String>();
+            for policy in POLICIES {
+                let once = policy.sanitize(&name);
+                assert_eq!(policy.sanitize(&once), once, "{:?} {:?}", policy, name);
+                assert_eq!(once.is_empty(), name.is_empty(), "{:?}", name);
+                assert!(once != "." && once != "..");
+                assert!(policy.len(&once) <= MAX_NAME_LEN, "{:?}", name);
+                assert!(!once.contains(|c: char| c == '/' || c.is_control()));
+                if policy.windows_rules() {
+                    assert!(!once.ends_with(['.', ' ']), "{:?}", name);
+                    assert!(!once.contains(WINDOWS_RESERVED_CHARS));
+                }
+            }
+        }
+    }
 }
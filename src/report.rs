@@ -0,0 +1,67 @@
+use std::{fmt::Display, path::PathBuf};
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncFailure {
+    pub what: String,
+    pub error: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct SyncReport {
+    pub downloaded: Vec<PathBuf>,
+    pub updated: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    pub deleted: Vec<PathBuf>,
+    pub links: Vec<PathBuf>,
+    pub linked: Vec<(PathBuf, PathBuf)>,
+    pub documents: Vec<PathBuf>,
+    pub failed: Vec<SyncFailure>,
+}
+
+impl SyncReport {
+    pub fn fail(&mut self, what: impl Into<String>, error: impl Display) {
+        self.failed.push(SyncFailure {
+            what: what.into(),
+            error: error.to_string(),
+        });
+    }
+    pub fn merge(&mut self, other: SyncReport) {
+        self.downloaded.extend(other.downloaded);
+        self.updated.extend(other.updated);
+        self.skipped.extend(other.skipped);
+        self.moved.extend(other.moved);
+        self.deleted.extend(other.deleted);
+        self.links.extend(other.links);
+        self.linked.extend(other.linked);
+        self.documents.extend(other.documents);
+        self.failed.extend(other.failed);
+    }
+    pub fn has_failures(&self) -> bool {
+        !self.failed.is_empty()
+    }
+}
+
+impl Display for SyncReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "downloaded: {}, updated: {}, skipped: {}, moved: {}, deleted: {}, links: {}, duplicates linked: {}, documents: {}, failed: {}",
+            self.downloaded.len(),
+            self.updated.len(),
+            self.skipped.len(),
+            self.moved.len(),
+            self.deleted.len(),
+            self.links.len(),
+            self.linked.len(),
+            self.documents.len(),
+            self.failed.len()
+        )?;
+        for failure in &self.failed {
+            writeln!(f, "  failed {}: {}", failure.what, failure.error)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+use log::warn;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of the backoff that is randomized, between 0 and 1.
+    pub jitter: f64,
+    pub retry_statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            retry_statuses: vec![408, 429, 500, 502, 503, 504],
+        }
+    }
+}
+
+pub trait Retryable {
+    fn is_retryable(&self, policy: &RetryPolicy) -> bool;
+}
+
+impl Retryable for reqwest::Error {
+    fn is_retryable(&self, policy: &RetryPolicy) -> bool {
+        if let Some(status) = self.status() {
+            return policy.is_retryable_status(status);
+        }
+        self.is_timeout() || self.is_connect() || self.is_request() || self.is_body()
+    }
+}
+
+/// A random number in `[0, 1)`, good enough for jitter.
+///
+/// Every `RandomState` is seeded with fresh random keys, so hashing a constant with a new one
+/// gives a new random value without pulling in a random number crate.
+fn random_unit() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl RetryPolicy {
+    pub fn is_retryable_status(&self, status: StatusCode) -> bool {
+        self.retry_statuses.contains(&status.as_u16())
+    }
+    /// Delay before retrying after failed attempt `attempt`, growing by `multiplier` from
+    /// `initial_backoff_ms`, randomized by `jitter` either way and at most `max_backoff_ms`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let base = self.initial_backoff_ms as f64 * self.multiplier.powi(attempt as i32 - 1);
+        let base = base.min(self.max_backoff_ms as f64);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = 1.0 - jitter + 2.0 * jitter * random_unit();
+        Duration::from_millis(((base * factor) as u64).min(self.max_backoff_ms))
+    }
+    /// Runs `f` until it succeeds, fails with a non-retryable error or runs out of attempts.
+    ///
+    /// The closure receives the 1-based attempt number.
+    pub async fn run<T, E, F, Fut>(&self, what: &str, mut f: F) -> Result<T, E>
+    where
+        E: Retryable + Display,
+        F: FnMut(u32) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f(attempt).await {
+                Err(e) if attempt < self.max_attempts && e.is_retryable(self) => {
+                    let delay = self.backoff(attempt);
+                    warn!(
+                        "{} failed (attempt {}/{}): {}, retrying in {:?}",
+                        what, attempt, self.max_attempts, e, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_exponentially_up_to_the_cap() {
+        let policy = RetryPolicy {
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+        let delays = (1..=8)
+            .map(|attempt| policy.backoff(attempt).as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, [500, 1000, 2000, 4000, 8000, 16000, 30000, 30000]);
+        assert_eq!(policy.backoff(1000), Duration::from_millis(30_000));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = RetryPolicy::default();
+        for _ in 0..1000 {
+            let delay = policy.backoff(2).as_millis();
+            assert!((800..=1200).contains(&delay), "{}", delay);
+            // Jitter never pushes a delay past the cap.
+            assert!(policy.backoff(10).as_millis() <= 30_000);
+        }
+        let unclamped = RetryPolicy {
+            jitter: 5.0,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..1000 {
+            assert!(unclamped.backoff(1).as_millis() <= 1000);
+        }
+    }
+
+    #[test]
+    fn random_unit_is_in_range_and_varies() {
+        let values = (0..100).map(|_| random_unit()).collect::<Vec<_>>();
+        assert!(values.iter().all(|v| (0.0..1.0).contains(v)));
+        assert!(values.iter().any(|v| *v != values[0]));
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::File;
+
+pub const STATE_DIR: &str = ".canvas-sync";
+pub const STATE_FILE: &str = "state.json";
+pub const TRASH_DIR: &str = "trash";
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileRecord {
+    pub id: i64,
+    pub updated_at: String,
+    pub modified_at: String,
+    pub size: i64,
+    pub path: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct StateData {
+    files: HashMap<i64, FileRecord>,
+    /// Paths written for pages, assignments, links and discussions, by item key.
+    #[serde(default)]
+    items: HashMap<String, Vec<PathBuf>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileStatus {
+    New,
+    Updated,
+    Moved {
+        from: PathBuf,
+    },
+    /// Tracked and unchanged on Canvas, but gone from disk.
+    Missing,
+    /// Tracked and unchanged on Canvas, but changed on disk.
+    LocallyModified,
+    Untracked,
+    UpToDate,
+}
+
+/// Changes are kept in memory until `save`, so tracking thousands of files does not rewrite
+/// the state file thousands of times.
+pub struct SyncState {
+    root: PathBuf,
+    data: Mutex<StateData>,
+    dirty: AtomicBool,
+}
+
+impl Debug for SyncState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncState")
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SyncState {
+    pub fn state_path(root: &Path) -> PathBuf {
+        root.join(STATE_DIR).join(STATE_FILE)
+    }
+    pub fn load(root: &Path) -> Self {
+        let path = Self::state_path(root);
+        let data = match std::fs::File::open(&path) {
+            Ok(file) => serde_json::from_reader(io::BufReader::new(file)).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to parse sync state {:?}, starting fresh: {}",
+                    path, e
+                );
+                StateData::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => StateData::default(),
+            Err(e) => {
+                warn!(
+                    "Failed to open sync state {:?}, starting fresh: {}",
+                    path, e
+                );
+                StateData::default()
+            }
+        };
+        let state = Self {
+            root: root.to_path_buf(),
+            data: Mutex::new(StateData::default()),
+            dirty: AtomicBool::new(false),
+        };
+        // Older state files stored paths including the root.
+        let files = data
+            .files
+            .into_iter()
+            .map(|(id, mut record)| {
+                record.path = state.relative(&record.path);
+                record.links = record.links.iter().map(|l| state.relative(l)).collect();
+                (id, record)
+            })
+            .collect();
+        *state.data.lock().unwrap() = StateData {
+            files,
+            items: data.items,
+        };
+        state
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    // Records are stored relative to the root so the synced folder can be moved around,
+    // but handed out with the root prepended.
+    fn relative(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.root).unwrap_or(path).to_path_buf()
+    }
+    fn resolve(&self, mut record: FileRecord) -> FileRecord {
+        record.path = self.root.join(&record.path);
+        for link in &mut record.links {
+            *link = self.root.join(&link);
+        }
+        record
+    }
+    pub fn get(&self, id: i64) -> Option<FileRecord> {
+        let record = self.data.lock().unwrap().files.get(&id).cloned();
+        record.map(|record| self.resolve(record))
+    }
+    /// Id of the file recorded as synced to `path`.
+    fn owner(&self, path: &Path) -> Option<i64> {
+        let path = self.relative(path);
+        let data = self.data.lock().unwrap();
+        data.files
+            .values()
+            .find(|record| record.path == path)
+            .map(|record| record.id)
+    }
+    pub fn check(&self, file: &File) -> Result<FileStatus, io::Error> {
+        let record = match self.get(file.id) {
+            Some(record) => record,
+            None => {
+                // Downloaded before the state database existed, adopt it as is, unless it is
+                // the copy of another file that used to be synced there.
+                if file.local_file_matches()? && self.owner(&file.local_path()).is_none() {
+                    return Ok(FileStatus::Untracked);
+                }
+                return Ok(FileStatus::New);
+            }
+        };
+        if record.updated_at != file.updated_at
+            || record.modified_at != file.modified_at
+            || record.size != file.size
+        {
+            return Ok(FileStatus::Updated);
+        }
+        if record.path != file.local_path() {
+            if record_file_matches(&record)? && !file.local_path().exists() {
+                return Ok(FileStatus::Moved { from: record.path });
+            }
+            return Ok(FileStatus::Updated);
+        }
+        if !file.local_path().exists() {
+            return Ok(FileStatus::Missing);
+        }
+        if !file.local_file_matches()? {
+            return Ok(FileStatus::LocallyModified);
+        }
+        Ok(FileStatus::UpToDate)
+    }
+    pub fn records(&self) -> Vec<FileRecord> {
+        let records = self
+            .data
+            .lock()
+            .unwrap()
+            .files
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        records
+            .into_iter()
+            .map(|record| self.resolve(record))
+            .collect()
+    }
+    pub fn trash_dir(&self) -> PathBuf {
+        self.root.join(STATE_DIR).join(TRASH_DIR)
+    }
+    fn touch(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+    pub fn remove(&self, id: i64) {
+        self.data.lock().unwrap().files.remove(&id);
+        self.touch();
+    }
+    pub fn record(&self, mut record: FileRecord) {
+        record.path = self.relative(&record.path);
+        record.links = record.links.iter().map(|l| self.relative(l)).collect();
+        self.data.lock().unwrap().files.insert(record.id, record);
+        self.touch();
+    }
+    pub fn add_link(&self, id: i64, link: PathBuf) {
+        let link = self.relative(&link);
+        let mut data = self.data.lock().unwrap();
+        if let Some(record) = data.files.get_mut(&id) {
+            if !record.links.contains(&link) {
+                record.links.push(link);
+                self.touch();
+            }
+        }
+    }
+    /// Drops the links of file `id` not in `keep`, returning them.
+    pub fn retain_links(&self, id: i64, keep: &[PathBuf]) -> Vec<PathBuf> {
+        let keep = keep.iter().map(|l| self.relative(l)).collect::<Vec<_>>();
+        let mut data = self.data.lock().unwrap();
+        let stale = match data.files.get_mut(&id) {
+            Some(record) => {
+                let (kept, stale) = record.links.drain(..).partition(|l| keep.contains(l));
+                record.links = kept;
+                stale
+            }
+            None => Vec::new(),
+        };
+        if !stale.is_empty() {
+            self.touch();
+        }
+        stale.into_iter().map(|l| self.root.join(l)).collect()
+    }
+    /// Paths last written for item `key`.
+    pub fn item(&self, key: &str) -> Vec<PathBuf> {
+        let data = self.data.lock().unwrap();
+        data.items
+            .get(key)
+            .map(|paths| paths.iter().map(|p| self.root.join(p)).collect())
+            .unwrap_or_default()
+    }
+    /// Sets the paths written for item `key`, returning the previously written ones that are gone.
+    pub fn record_item(&self, key: &str, paths: &[PathBuf]) -> Vec<PathBuf> {
+        let paths = paths.iter().map(|p| self.relative(p)).collect::<Vec<_>>();
+        let mut data = self.data.lock().unwrap();
+        let old = data.items.insert(key.to_string(), paths.clone());
+        if old.as_ref() == Some(&paths) {
+            return Vec::new();
+        }
+        self.touch();
+        old.unwrap_or_default()
+            .into_iter()
+            .filter(|p| !paths.contains(p))
+            .map(|p| self.root.join(p))
+            .collect()
+    }
+    /// Writes the state file if anything changed since it was last saved.
+    pub fn save(&self) -> Result<(), io::Error> {
+        let data = self.data.lock().unwrap();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        self.save_locked(&data).inspect_err(|_| self.touch())
+    }
+    fn save_locked(&self, data: &StateData) -> Result<(), io::Error> {
+        let path = Self::state_path(&self.root);
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        let tmp = dir.join(format!("{}.tmp", STATE_FILE));
+        let json = serde_json::to_vec_pretty(data)?;
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(&tmp, &path)
+    }
+}
+
+fn record_file_matches(record: &FileRecord) -> Result<bool, io::Error> {
+    match record.path.metadata() {
+        Ok(metadata) => Ok(metadata.len() == record.size as u64),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+impl From<&File> for FileRecord {
+    fn from(file: &File) -> Self {
+        Self {
+            id: file.id,
+            updated_at: file.updated_at.clone(),
+            modified_at: file.modified_at.clone(),
+            size: file.size,
+            path: file.local_path(),
+            links: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("canvas-sync-state-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file(root: &Path, id: i64, name: &str, size: i64) -> File {
+        File {
+            id,
+            root: root.to_path_buf(),
+            sanitize: Default::default(),
+            folder_path: Vec::new(),
+            file_name: name.to_string(),
+            content_type: String::new(),
+            size,
+            created_at: String::new(),
+            updated_at: String::new(),
+            modified_at: String::new(),
+            url: String::new(),
+        }
+    }
+
+    #[test]
+    fn adopts_untracked_copies_only_at_unowned_paths() {
+        let root = temp_dir("adopt");
+        std::fs::write(root.join("Syllabus.pdf"), "old!").unwrap();
+        let state = SyncState::load(&root);
+        let new = file(&root, 2, "Syllabus.pdf", 4);
+        assert_eq!(state.check(&new).unwrap(), FileStatus::Untracked);
+
+        // The same bytes were downloaded for another file, which was since replaced.
+        state.record(FileRecord::from(&file(&root, 1, "Syllabus.pdf", 4)));
+        assert_eq!(state.check(&new).unwrap(), FileStatus::New);
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn changes_are_written_on_save() {
+        let root = temp_dir("save");
+        let state = SyncState::load(&root);
+        state.record(FileRecord::from(&file(&root, 1, "a.pdf", 1)));
+        state.record_item("item:1", &[root.join("page.html")]);
+        assert!(!SyncState::state_path(&root).exists());
+
+        state.save().unwrap();
+        let loaded = SyncState::load(&root);
+        assert_eq!(loaded.get(1).unwrap().path, root.join("a.pdf"));
+        assert_eq!(loaded.item("item:1"), vec![root.join("page.html")]);
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
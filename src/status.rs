@@ -0,0 +1,145 @@
+use std::{fmt::Display, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{
+    canvas_api::Client,
+    report::SyncFailure,
+    state::FileStatus,
+    sync::{plan_sync, PruneMode, SyncAction, SyncConfig},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    New,
+    Updated,
+    Moved,
+    Missing,
+    LocallyModified,
+    Orphaned,
+}
+
+impl Display for ChangeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let label = match self {
+            ChangeKind::New => "new",
+            ChangeKind::Updated => "updated",
+            ChangeKind::Moved => "moved",
+            ChangeKind::Missing => "missing",
+            ChangeKind::LocallyModified => "locally modified",
+            ChangeKind::Orphaned => "orphaned",
+        };
+        f.pad(label)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileChange {
+    pub kind: ChangeKind,
+    pub id: i64,
+    pub path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
+    pub size: i64,
+}
+
+/// How the local copy of a course differs from Canvas.
+#[derive(Debug, Clone, Serialize)]
+pub struct CourseStatus {
+    pub course: i64,
+    pub path: PathBuf,
+    pub changes: Vec<FileChange>,
+    pub up_to_date: usize,
+    pub failed: Vec<SyncFailure>,
+}
+
+impl CourseStatus {
+    pub fn count(&self, kind: ChangeKind) -> usize {
+        self.changes.iter().filter(|c| c.kind == kind).count()
+    }
+}
+
+impl Display for CourseStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "course {} -> {:?}", self.course, self.path)?;
+        for change in &self.changes {
+            write!(f, "  {:<16}  {:?}", change.kind, change.path)?;
+            if let Some(from) = &change.from {
+                write!(f, " (from {:?})", from)?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "  up to date: {}", self.up_to_date)?;
+        for kind in [
+            ChangeKind::New,
+            ChangeKind::Updated,
+            ChangeKind::Moved,
+            ChangeKind::Missing,
+            ChangeKind::LocallyModified,
+            ChangeKind::Orphaned,
+        ] {
+            write!(f, ", {}: {}", kind, self.count(kind))?;
+        }
+        writeln!(f)?;
+        for failure in &self.failed {
+            writeln!(f, "  failed {}: {}", failure.what, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Compares the local copy of a course with Canvas without changing anything.
+pub async fn course_status(config: &SyncConfig, client: &Client) -> CourseStatus {
+    // Plan as if pruning, so files gone from Canvas show up as deletions.
+    let config = SyncConfig {
+        prune: Some(PruneMode::Delete),
+        ..config.clone()
+    };
+    let plan = plan_sync(&config, client).await;
+
+    let mut status = CourseStatus {
+        course: config.courseid,
+        path: config.path.clone(),
+        changes: Vec::new(),
+        up_to_date: plan.report.skipped.len(),
+        failed: plan.report.failed.clone(),
+    };
+    for action in plan.actions {
+        let change = match action {
+            SyncAction::Download { file, status } => FileChange {
+                kind: match status {
+                    FileStatus::New => ChangeKind::New,
+                    FileStatus::Missing => ChangeKind::Missing,
+                    FileStatus::LocallyModified => ChangeKind::LocallyModified,
+                    _ => ChangeKind::Updated,
+                },
+                id: file.id,
+                path: file.local_path(),
+                from: None,
+                size: file.size,
+            },
+            SyncAction::Move { file, from } => FileChange {
+                kind: ChangeKind::Moved,
+                id: file.id,
+                path: file.local_path(),
+                from: Some(from),
+                size: file.size,
+            },
+            SyncAction::Delete { record, .. } => FileChange {
+                kind: ChangeKind::Orphaned,
+                id: record.id,
+                path: record.path,
+                from: None,
+                size: record.size,
+            },
+            SyncAction::Track { .. } => {
+                status.up_to_date += 1;
+                continue;
+            }
+            _ => continue,
+        };
+        status.changes.push(change);
+    }
+    status
+}
@@ -1,12 +1,34 @@
-use futures::StreamExt;
-use log::{debug, error, warn};
-use std::{collections::HashMap, path::PathBuf};
+use futures::{Stream, StreamExt};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
 use tokio::sync::Mutex;
 
 use crate::{
-    canvas_api::Client,
-    download::Downloader,
-    path::{sanitize_file_name, write_url_file},
+    canvas_api::{
+        assignments::AssignmentResp,
+        discussions::DiscussionTopicResp,
+        files::FileResp,
+        modules::{ModuleItemResp, ModuleResp},
+        Client, Error,
+    },
+    document::{
+        discussion_html, linked_file_ids, metadata_html, rewrite_file_links, with_extension,
+        write_document,
+    },
+    download::{DownloadTask, Downloader},
+    layout::{segments_path, CourseLayout, Layout},
+    path::{link_or_copy, remove_empty_dirs, url_file_path, write_url_file},
+    report::SyncReport,
+    state::{FileRecord, FileStatus, SyncState},
     File,
 };
 
@@ -14,6 +36,12 @@ pub struct IndentStack<T> {
     stack: Vec<(i64, T)>,
 }
 
+impl<T> Default for IndentStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> IndentStack<T> {
     pub fn new() -> Self {
         Self { stack: Vec::new() }
@@ -27,12 +55,923 @@ impl<T> IndentStack<T> {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PruneMode {
+    Delete,
+    Trash,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncMode {
+    Files,
+    Modules,
+    Both,
+}
+
+#[derive(Debug, Clone)]
 pub struct SyncConfig {
     pub courseid: i64,
     pub path: PathBuf,
+    pub mode: SyncMode,
+    pub prune: Option<PruneMode>,
+    pub markdown: bool,
+    pub discussions: bool,
+    pub layout: Layout,
+}
+
+#[derive(Debug)]
+pub enum SyncAction {
+    Download {
+        file: File,
+        status: FileStatus,
+    },
+    Track {
+        file: File,
+    },
+    Move {
+        file: File,
+        from: PathBuf,
+    },
+    LinkCopy {
+        id: i64,
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// `key` identifies the Canvas item across runs, so a renamed item replaces its old copy.
+    WriteLink {
+        key: String,
+        url: String,
+        title: String,
+        path: PathBuf,
+    },
+    WriteDocument {
+        key: String,
+        title: String,
+        body: String,
+        path: PathBuf,
+        markdown: bool,
+    },
+    Delete {
+        record: FileRecord,
+        mode: PruneMode,
+    },
+}
+
+impl SyncAction {
+    /// The Canvas item a `WriteDocument` or `WriteLink` is written for.
+    fn item_key(&self) -> Option<&str> {
+        match self {
+            SyncAction::WriteDocument { key, .. } | SyncAction::WriteLink { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+    /// The paths a `WriteDocument` or `WriteLink` writes to.
+    fn item_paths(&self) -> Vec<PathBuf> {
+        match self {
+            SyncAction::WriteDocument { path, markdown, .. } => {
+                let mut paths = vec![with_extension(path, "html")];
+                if *markdown {
+                    paths.push(with_extension(path, "md"));
+                }
+                paths
+            }
+            SyncAction::WriteLink { path, .. } => vec![url_file_path(path)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Display for SyncAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncAction::Download { file, status } => {
+                let verb = match status {
+                    FileStatus::New => "download",
+                    _ => "update",
+                };
+                write!(f, "{} {:?} ({} bytes)", verb, file.local_path(), file.size)
+            }
+            SyncAction::Track { file } => write!(f, "track {:?}", file.local_path()),
+            SyncAction::Move { file, from } => {
+                write!(f, "move {:?} -> {:?}", from, file.local_path())
+            }
+            SyncAction::LinkCopy { from, to, .. } => write!(f, "link {:?} -> {:?}", to, from),
+            SyncAction::WriteLink { url, path, .. } => write!(f, "link {:?} -> {}", path, url),
+            SyncAction::WriteDocument { path, .. } => write!(f, "write {:?}", path),
+            SyncAction::Delete { record, mode } => match mode {
+                PruneMode::Delete => write!(f, "delete {:?}", record.path),
+                PruneMode::Trash => write!(f, "trash {:?}", record.path),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SyncPlan {
+    pub actions: Vec<SyncAction>,
+    pub report: SyncReport,
+    state: Arc<SyncState>,
+    /// Whether everything on Canvas was listed, so anything not planned is gone.
+    complete: bool,
+    /// Collision keys of all paths the plan writes, which must not be removed as stale.
+    claimed: HashSet<String>,
+}
+
+impl SyncPlan {
+    pub fn total_bytes(&self) -> u64 {
+        self.actions
+            .iter()
+            .map(|action| match action {
+                SyncAction::Download { file, .. } => file.size as u64,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+struct Planner {
+    state: Arc<SyncState>,
+    layout: CourseLayout,
+    actions: std::sync::Mutex<Vec<SyncAction>>,
+    report: std::sync::Mutex<SyncReport>,
+    files: std::sync::Mutex<Vec<File>>,
+    items: std::sync::Mutex<Vec<(SyncAction, HashMap<i64, PathBuf>)>>,
+    complete: AtomicBool,
+}
+
+impl Planner {
+    fn new(config: &SyncConfig, layout: CourseLayout) -> Self {
+        Self {
+            state: Arc::new(SyncState::load(&config.path)),
+            layout,
+            actions: std::sync::Mutex::new(Vec::new()),
+            report: std::sync::Mutex::new(SyncReport::default()),
+            files: std::sync::Mutex::new(Vec::new()),
+            items: std::sync::Mutex::new(Vec::new()),
+            complete: AtomicBool::new(true),
+        }
+    }
+    fn fail(&self, what: impl Into<String>, error: impl Display) {
+        let what = what.into();
+        error!("Failed {}: {}", what, error);
+        self.report.lock().unwrap().fail(what, error);
+    }
+    fn fail_listing(&self, what: impl Into<String>, error: impl Display) {
+        self.complete.store(false, Ordering::Relaxed);
+        self.fail(what, error);
+    }
+    fn add(&self, action: SyncAction) {
+        self.actions.lock().unwrap().push(action);
+    }
+    fn place(&self, file: &mut File) {
+        file.root = self.state.root().to_path_buf();
+        file.sanitize = self.layout.sanitize();
+    }
+    fn add_file(&self, mut file: File) {
+        self.place(&mut file);
+        self.files.lock().unwrap().push(file);
+    }
+    /// Adds a `WriteDocument` or `WriteLink`. Links in a document to the `linked` files are
+    /// pointed at them once their final paths are known.
+    fn add_item(&self, action: SyncAction, linked: HashMap<i64, PathBuf>) {
+        self.items.lock().unwrap().push((action, linked));
+    }
+    /// Whether `claimant` was synced to the path with collision key `key` before.
+    fn synced_to(&self, claimant: &Claimant, key: &str) -> bool {
+        match claimant {
+            Claimant::File(id) => self
+                .state
+                .get(*id)
+                .is_some_and(|record| collision_key(&record.path) == key),
+            Claimant::Item(item) => self
+                .state
+                .item(item)
+                .iter()
+                .any(|path| collision_key(path) == key),
+        }
+    }
+    /// Gives files, documents and links that would end up at the same path, ignoring case,
+    /// distinct names by appending their id. The one already synced to that path, or else
+    /// a file before an item and the oldest one first, keeps the plain name so the names
+    /// stay the same from run to run.
+    fn disambiguate(
+        &self,
+        files: &mut [File],
+        items: &mut [(SyncAction, HashMap<i64, PathBuf>)],
+    ) -> HashMap<(i64, PathBuf), PathBuf> {
+        let mut claims = HashMap::<String, Vec<Claimant>>::new();
+        let mut claim = |path: &Path, claimant: Claimant| {
+            let claimants = claims.entry(collision_key(path)).or_default();
+            if !claimants.contains(&claimant) {
+                claimants.push(claimant);
+            }
+        };
+        for file in files.iter() {
+            claim(&file.local_path(), Claimant::File(file.id));
+        }
+        for (action, _) in items.iter() {
+            if let Some(key) = action.item_key() {
+                for path in action.item_paths() {
+                    claim(&path, Claimant::Item(key.to_string()));
+                }
+            }
+        }
+        let loses = |path: &Path, claimant: &Claimant| {
+            let key = collision_key(path);
+            let claimants = &claims[&key];
+            let owner = claimants
+                .iter()
+                .filter(|c| self.synced_to(c, &key))
+                .min()
+                .or_else(|| claimants.iter().min());
+            claimants.len() > 1 && owner != Some(claimant)
+        };
+
+        let mut renamed = HashMap::new();
+        for file in files.iter_mut() {
+            let path = file.local_path();
+            if !loses(&path, &Claimant::File(file.id)) {
+                continue;
+            }
+            file.file_name = file
+                .sanitize
+                .sanitize_with_id(&file.file_name, file.id, true);
+            warn!(
+                "{:?} is used by several files, saving file {} as {:?}",
+                path,
+                file.id,
+                file.local_path()
+            );
+            renamed.insert((file.id, path), file.local_path());
+        }
+        for (action, _) in items.iter_mut() {
+            let claimant = match action.item_key() {
+                Some(key) => Claimant::Item(key.to_string()),
+                None => continue,
+            };
+            if !action
+                .item_paths()
+                .iter()
+                .any(|path| loses(path, &claimant))
+            {
+                continue;
+            }
+            if let SyncAction::WriteDocument { key, path, .. }
+            | SyncAction::WriteLink { key, path, .. } = action
+            {
+                let id = key.rsplit_once(':').map_or(key.as_str(), |(_, id)| id);
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                let name = self.layout.sanitize().sanitize_with_id(&name, id, false);
+                let old = std::mem::replace(path, path.with_file_name(name));
+                warn!(
+                    "{:?} is used by several items, saving {} as {:?}",
+                    old, key, path
+                );
+            }
+        }
+        renamed
+    }
+    fn plan_file(
+        &self,
+        file: File,
+        primary: &mut HashMap<i64, PathBuf>,
+        copies: &mut Vec<(i64, PathBuf, PathBuf)>,
+        claimed: &HashSet<String>,
+    ) {
+        let path = file.local_path();
+        match primary.entry(file.id) {
+            Entry::Occupied(existing) if *existing.get() == path => {
+                debug!("File already planned: {:?}", file);
+                return;
+            }
+            Entry::Occupied(existing) => {
+                // The same file shows up in another place, download it once and link it there.
+                let copy = (file.id, existing.get().clone(), path);
+                if !copies.contains(&copy) {
+                    copies.push(copy);
+                }
+                return;
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(path);
+            }
+        }
+        match self.state.check(&file) {
+            Ok(FileStatus::UpToDate) => {
+                debug!("File already up to date: {:?}", file);
+                self.report.lock().unwrap().skipped.push(file.local_path());
+            }
+            Ok(FileStatus::Untracked) => self.add(SyncAction::Track { file }),
+            // Both used to be saved there, so what is on disk may well be the other one.
+            Ok(FileStatus::Moved { from }) if claimed.contains(&collision_key(&from)) => {
+                self.add(SyncAction::Download {
+                    file,
+                    status: FileStatus::Updated,
+                })
+            }
+            Ok(FileStatus::Moved { from }) => self.add(SyncAction::Move { file, from }),
+            Ok(status) => self.add(SyncAction::Download { file, status }),
+            Err(e) => self.fail(format!("checking local file {:?}", file.local_path()), e),
+        }
+    }
+    fn finish(self, config: &SyncConfig) -> SyncPlan {
+        let mut files = std::mem::take(&mut *self.files.lock().unwrap());
+        let mut items = std::mem::take(&mut *self.items.lock().unwrap());
+        let renamed = self.disambiguate(&mut files, &mut items);
+        let mut claimed = files
+            .iter()
+            .map(|file| collision_key(&file.local_path()))
+            .chain(
+                items
+                    .iter()
+                    .flat_map(|(action, _)| action.item_paths())
+                    .map(|path| collision_key(&path)),
+            )
+            .collect::<HashSet<_>>();
+        let mut primary = HashMap::new();
+        let mut copies = Vec::new();
+        for file in files {
+            self.plan_file(file, &mut primary, &mut copies, &claimed);
+        }
+        for (mut action, linked) in items {
+            if let SyncAction::WriteDocument { body, .. } = &mut action {
+                let local = linked
+                    .into_iter()
+                    .filter_map(|(id, path)| {
+                        let path = renamed.get(&(id, path.clone())).unwrap_or(&path);
+                        Some((id, path.file_name()?.to_string_lossy().into_owned()))
+                    })
+                    .collect();
+                *body = rewrite_file_links(body, config.courseid, &local);
+            }
+            self.add(action);
+        }
+        claimed.extend(copies.iter().map(|(_, _, to)| collision_key(to)));
+
+        let mut actions = self.actions.into_inner().unwrap();
+        for (id, from, to) in copies {
+            actions.push(SyncAction::LinkCopy { id, from, to });
+        }
+        if let Some(mode) = config.prune {
+            if self.complete.load(Ordering::Relaxed) {
+                for record in self.state.records() {
+                    if !primary.contains_key(&record.id) {
+                        actions.push(SyncAction::Delete { record, mode });
+                    }
+                }
+            } else {
+                warn!("Not pruning because the remote listing was incomplete");
+            }
+        }
+        SyncPlan {
+            actions,
+            report: self.report.into_inner().unwrap(),
+            state: self.state,
+            complete: self.complete.into_inner(),
+            claimed,
+        }
+    }
+}
+
+/// A file, or the Canvas item a document or link is written for, claiming a path.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Claimant {
+    File(i64),
+    Item(String),
+}
+
+fn collision_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Deletes or trashes the copies of a file removed on Canvas, leaving the paths `claimed` by
+/// files of this plan alone.
+fn prune_file(
+    state: &SyncState,
+    record: &FileRecord,
+    mode: PruneMode,
+    claimed: &HashSet<String>,
+) -> std::io::Result<()> {
+    let unclaimed = |path: &Path| path.exists() && !claimed.contains(&collision_key(path));
+    if unclaimed(&record.path) {
+        match mode {
+            PruneMode::Delete => {
+                info!("Deleting file removed on remote: {:?}", record.path);
+                std::fs::remove_file(&record.path)?;
+            }
+            PruneMode::Trash => {
+                let relative = record
+                    .path
+                    .strip_prefix(state.root())
+                    .unwrap_or(&record.path);
+                let trash = state.trash_dir().join(relative);
+                info!(
+                    "Trashing file removed on remote: {:?} -> {:?}",
+                    record.path, trash
+                );
+                move_file(&record.path, &trash)?;
+            }
+        }
+    }
+    for link in &record.links {
+        if unclaimed(link) {
+            info!("Deleting link to file removed on remote: {:?}", link);
+            std::fs::remove_file(link)?;
+        }
+    }
+    state.remove(record.id);
+    Ok(())
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::rename(from, to)
+}
+
+/// Moves the copy of a file that changed and moved on Canvas to its new path before it is
+/// updated there, so the old copy doesn't linger untracked. Returns where it was if it moved.
+fn take_old_copy(
+    state: &SyncState,
+    file: &File,
+    claimed: &HashSet<String>,
+) -> std::io::Result<Option<PathBuf>> {
+    let to = file.local_path();
+    let old = match state.get(file.id) {
+        Some(old) if old.path != to && old.path.exists() => old,
+        _ => return Ok(None),
+    };
+    // Another file of this plan is saved there now.
+    if claimed.contains(&collision_key(&old.path)) {
+        return Ok(None);
+    }
+    let from = old.path.clone();
+    let moved = !to.exists();
+    if moved {
+        info!("File moved on remote: {:?} -> {:?}", from, to);
+        move_file(&from, &to)?;
+    } else {
+        info!("Removing old copy of updated file: {:?}", from);
+        std::fs::remove_file(&from)?;
+    }
+    if let Some(parent) = from.parent() {
+        remove_empty_dirs(parent, state.root());
+    }
+    // Still the old version, so the next run updates it if this download fails.
+    state.record(FileRecord { path: to, ..old });
+    Ok(moved.then_some(from))
+}
+
+/// Removes the `stale` copies left behind by an earlier run, along with folders left empty,
+/// except where something else of this plan is saved now.
+fn remove_stale(
+    state: &SyncState,
+    stale: Vec<PathBuf>,
+    claimed: &HashSet<String>,
+    what: &str,
+) -> std::io::Result<()> {
+    for stale in stale {
+        if stale.exists() && !claimed.contains(&collision_key(&stale)) {
+            info!("Removing {}: {:?}", what, stale);
+            std::fs::remove_file(&stale)?;
+        }
+        if let Some(parent) = stale.parent() {
+            remove_empty_dirs(parent, state.root());
+        }
+    }
+    Ok(())
+}
+
+/// Records the paths written for an item and removes the ones it had before but not anymore.
+fn replace_item(
+    state: &SyncState,
+    key: &str,
+    written: &[PathBuf],
+    claimed: &HashSet<String>,
+) -> std::io::Result<()> {
+    let stale = state.record_item(key, written);
+    remove_stale(state, stale, claimed, "old copy of renamed item")
+}
+
+fn write_link(url: &str, title: &str, path: &Path) -> Result<PathBuf, std::io::Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_url_file(url, title, path.to_str().expect("Invalid path"))
+}
+
+pub fn execute_plan(plan: SyncPlan, downloader: &Downloader) -> SyncReport {
+    let state = &plan.state;
+    let mut report = plan.report;
+
+    let mut pending_links = HashMap::<PathBuf, Vec<PathBuf>>::new();
+    let mut planned_links = HashMap::<i64, Vec<PathBuf>>::new();
+    for action in &plan.actions {
+        if let SyncAction::LinkCopy { id, from, to } = action {
+            pending_links
+                .entry(from.clone())
+                .or_default()
+                .push(to.clone());
+            planned_links.entry(*id).or_default().push(to.clone());
+        }
+    }
+    // Copies left behind where a linking item used to be, e.g. before its module was renumbered
+    // or after the file was taken out of it. With an incomplete listing only the files linked
+    // somewhere in this run are known to have all their links planned.
+    for record in state.records() {
+        let id = record.id;
+        if record.links.is_empty() || !(plan.complete || planned_links.contains_key(&id)) {
+            continue;
+        }
+        let links = planned_links.remove(&id).unwrap_or_default();
+        let stale = state.retain_links(id, &links);
+        if let Err(e) = remove_stale(state, stale, &plan.claimed, "old link") {
+            error!("Failed removing old links to file {}: {}", id, e);
+            report.fail(format!("removing old links to file {}", id), e);
+        }
+    }
+
+    for action in plan.actions {
+        match action {
+            SyncAction::Download { file, status } => {
+                match take_old_copy(state, &file, &plan.claimed) {
+                    Ok(Some(from)) => report.moved.push((from, file.local_path())),
+                    Ok(None) => {}
+                    Err(e) => {
+                        error!("Failed moving old copy of {:?}: {}", file.local_path(), e);
+                        report.fail(format!("moving old copy of {:?}", file.local_path()), e);
+                    }
+                }
+                let record = FileRecord::from(&file);
+                let links = pending_links.remove(&file.local_path());
+                let mut task = DownloadTask::from(file).with_state(state, record);
+                task.update = status != FileStatus::New;
+                task.links = links.unwrap_or_default();
+                downloader.submit(task);
+            }
+            SyncAction::LinkCopy { id, from, to } => {
+                // Links to files being downloaded are made by the downloader once they finish.
+                if !pending_links.contains_key(&from) {
+                    continue;
+                }
+                let result = link_or_copy(&from, &to).inspect(|_| state.add_link(id, to.clone()));
+                match result {
+                    Ok(true) => report.linked.push((from, to)),
+                    Ok(false) => {}
+                    Err(e) => {
+                        error!("Failed linking {:?} to {:?}: {}", to, from, e);
+                        report.fail(format!("linking {:?} to {:?}", to, from), e);
+                    }
+                }
+            }
+            SyncAction::Track { file } => {
+                state.record(FileRecord::from(&file));
+                report.skipped.push(file.local_path());
+            }
+            SyncAction::Move { file, from } => {
+                let to = file.local_path();
+                info!("File moved on remote: {:?} -> {:?}", from, to);
+                match move_file(&from, &to) {
+                    Ok(_) => {
+                        state.record(FileRecord::from(&file));
+                        if let Some(parent) = from.parent() {
+                            remove_empty_dirs(parent, state.root());
+                        }
+                        report.moved.push((from, to));
+                    }
+                    Err(e) => {
+                        error!("Failed moving {:?} to {:?}: {}", from, to, e);
+                        report.fail(format!("moving {:?} to {:?}", from, to), e);
+                    }
+                }
+            }
+            SyncAction::WriteLink {
+                key,
+                url,
+                title,
+                path,
+            } => match write_link(&url, &title, &path)
+                .and_then(|written| replace_item(state, &key, &[written], &plan.claimed))
+            {
+                Ok(_) => report.links.push(path),
+                Err(e) => {
+                    error!("Failed writing link file {:?}: {}", path, e);
+                    report.fail(format!("writing link file {:?}", path), e);
+                }
+            },
+            SyncAction::WriteDocument {
+                key,
+                title,
+                body,
+                path,
+                markdown,
+            } => match write_document(&path, &title, &body, markdown).and_then(|written| {
+                replace_item(state, &key, &written, &plan.claimed).map(|_| written)
+            }) {
+                Ok(written) => report.documents.extend(written),
+                Err(e) => {
+                    error!("Failed writing document {:?}: {}", path, e);
+                    report.fail(format!("writing document {:?}", path), e);
+                }
+            },
+            SyncAction::Delete { record, mode } => {
+                match prune_file(state, &record, mode, &plan.claimed) {
+                    Ok(_) => report.deleted.push(record.path),
+                    Err(e) => {
+                        error!("Failed pruning {:?}: {}", record.path, e);
+                        report.fail(format!("pruning {:?}", record.path), e);
+                    }
+                }
+            }
+        }
+    }
+    // Downloads still to finish are saved by the downloader.
+    if let Err(e) = state.save() {
+        error!("Failed saving sync state: {}", e);
+        report.fail(
+            format!(
+                "saving sync state {:?}",
+                SyncState::state_path(state.root())
+            ),
+            e,
+        );
+    }
+    report
+}
+
+pub(crate) fn module_item_path(
+    config: &SyncConfig,
+    layout: &CourseLayout,
+    module: &ModuleResp,
+    headers: &[String],
+    item: &ModuleItemResp,
+    name: &str,
+) -> PathBuf {
+    segments_path(
+        &config.path,
+        &layout.module_item(module, headers, item, name, ""),
+    )
 }
 
-pub async fn download_modules(config: &SyncConfig, client: &Client, downloader: &Downloader) {
+pub async fn plan_sync(config: &SyncConfig, client: &Client) -> SyncPlan {
+    let planner = match CourseLayout::fetch(&config.layout, config.courseid, client).await {
+        Ok(layout) => Planner::new(config, layout),
+        Err(e) => {
+            // Without the course details paths can't be laid out, so don't plan anything.
+            let planner = Planner::new(
+                config,
+                CourseLayout::new(config.layout.clone(), config.courseid, None),
+            );
+            planner.fail_listing("getting course", e);
+            return planner.finish(config);
+        }
+    };
+    match config.mode {
+        SyncMode::Files => collect_files(config, client, &planner).await,
+        SyncMode::Modules => collect_modules(config, client, &planner).await,
+        SyncMode::Both => {
+            collect_files(config, client, &planner).await;
+            collect_modules(config, client, &planner).await;
+        }
+    }
+    if config.discussions {
+        collect_discussions(config, client, &planner).await;
+    }
+    planner.finish(config)
+}
+
+pub async fn download_modules(
+    config: &SyncConfig,
+    client: &Client,
+    downloader: &Downloader,
+) -> SyncReport {
+    execute_plan(plan_modules(config, client).await, downloader)
+}
+
+pub async fn download_files(
+    config: &SyncConfig,
+    client: &Client,
+    downloader: &Downloader,
+) -> SyncReport {
+    execute_plan(plan_files(config, client).await, downloader)
+}
+
+fn fix_download_url(client: &Client, file: &mut FileResp) {
+    if file.url.is_empty() {
+        file.url = client.build_url(
+            format!(
+                "/files/{}/download?download_frd=1&verifier={}",
+                file.id, file.uuid
+            )
+            .as_str(),
+        );
+        warn!(
+            "No url for file: {:?}, trying to guess as {}",
+            file.display_name, file.url
+        );
+    }
+}
+
+async fn fetch_course_file(
+    config: &SyncConfig,
+    client: &Client,
+    fileid: i64,
+) -> Result<File, Error> {
+    let mut file = client.get_course_file(config.courseid, fileid).await?;
+    fix_download_url(client, &mut file);
+    debug!("File: {:?}", file);
+
+    Ok(File::from(file))
+}
+
+/// Plans downloads of the course files an HTML body links to into `folder_path`,
+/// and returns the body with those links pointing at the local copies.
+async fn plan_linked_files(
+    config: &SyncConfig,
+    client: &Client,
+    planner: &Planner,
+    body: &str,
+    folder_path: &[String],
+) -> HashMap<i64, PathBuf> {
+    let mut local = HashMap::new();
+    for fileid in linked_file_ids(body, config.courseid) {
+        match fetch_course_file(config, client, fileid).await {
+            Ok(mut file) => {
+                file.folder_path = folder_path.to_vec();
+                planner.place(&mut file);
+                local.insert(fileid, file.local_path());
+                planner.add_file(file);
+            }
+            // The link stays pointed at Canvas, but the file must not be pruned.
+            Err(e) => planner.fail_listing(
+                format!("getting linked file {}, keeping remote link", fileid),
+                e,
+            ),
+        }
+    }
+    local
+}
+
+fn assignment_metadata(assignment: &AssignmentResp) -> String {
+    let mut entries = Vec::new();
+    if let Some(due_at) = &assignment.due_at {
+        entries.push(("Due", due_at.clone()));
+    }
+    if let Some(unlock_at) = &assignment.unlock_at {
+        entries.push(("Available from", unlock_at.clone()));
+    }
+    if let Some(lock_at) = &assignment.lock_at {
+        entries.push(("Locks", lock_at.clone()));
+    }
+    if let Some(points) = assignment.points_possible {
+        entries.push(("Points", points.to_string()));
+    }
+    if !assignment.submission_types.is_empty() {
+        entries.push(("Submission types", assignment.submission_types.join(", ")));
+    }
+    entries.push(("Link", assignment.html_url.clone()));
+    metadata_html(&entries)
+}
+
+async fn plan_module_item(
+    config: &SyncConfig,
+    client: &Client,
+    planner: &Planner,
+    module: &ModuleResp,
+    headers: &[String],
+    item: ModuleItemResp,
+) {
+    match item.type_.as_str() {
+        "File" => {
+            let fileid = match item.content_id {
+                Some(id) => id,
+                None => {
+                    planner.fail_listing(
+                        format!("getting {:?}", item.title),
+                        "module item has no content id",
+                    );
+                    return;
+                }
+            };
+            match fetch_course_file(config, client, fileid).await {
+                Ok(mut file) => {
+                    let segments = planner.layout.module_item(
+                        module,
+                        headers,
+                        &item,
+                        &file.file_name,
+                        &file.content_type,
+                    );
+                    file.set_path_segments(segments);
+                    planner.add_file(file);
+                }
+                Err(e) => planner.fail_listing(format!("getting file {}", fileid), e),
+            }
+        }
+        "Page" => {
+            let page_url = match &item.page_url {
+                Some(page_url) => page_url,
+                None => return,
+            };
+            match client.get_course_page(config.courseid, page_url).await {
+                Ok(page) => {
+                    let mut segments =
+                        planner
+                            .layout
+                            .module_item(module, headers, &item, &page.title, "");
+                    let path = segments_path(&config.path, &segments);
+                    segments.pop();
+                    let body = page.body.or(page.lock_explanation).unwrap_or_default();
+                    let linked = plan_linked_files(config, client, planner, &body, &segments).await;
+                    planner.add_item(
+                        SyncAction::WriteDocument {
+                            key: format!("item:{}", item.id),
+                            title: page.title,
+                            body,
+                            path,
+                            markdown: config.markdown,
+                        },
+                        linked,
+                    );
+                }
+                Err(e) => planner.fail_listing(format!("getting page {:?}", item.title), e),
+            }
+        }
+        "Assignment" => {
+            let assignmentid = match item.content_id {
+                Some(id) => id,
+                None => {
+                    planner.fail_listing(
+                        format!("getting {:?}", item.title),
+                        "module item has no content id",
+                    );
+                    return;
+                }
+            };
+            match client
+                .get_course_assignment(config.courseid, assignmentid)
+                .await
+            {
+                Ok(assignment) => {
+                    let mut segments =
+                        planner
+                            .layout
+                            .module_item(module, headers, &item, &assignment.name, "");
+                    let path = segments_path(&config.path, &segments);
+                    segments.pop();
+                    let description = assignment
+                        .description
+                        .clone()
+                        .or(assignment.lock_explanation.clone())
+                        .unwrap_or_default();
+                    let linked =
+                        plan_linked_files(config, client, planner, &description, &segments).await;
+                    planner.add_item(
+                        SyncAction::WriteDocument {
+                            key: format!("item:{}", item.id),
+                            body: assignment_metadata(&assignment) + &description,
+                            title: assignment.name,
+                            path,
+                            markdown: config.markdown,
+                        },
+                        linked,
+                    );
+                }
+                Err(e) => planner.fail_listing(format!("getting assignment {:?}", item.title), e),
+            }
+        }
+        "ExternalUrl" | "ExternalTool" => {
+            if let Some(url) = &item.url {
+                let path =
+                    module_item_path(config, &planner.layout, module, headers, &item, &item.title);
+                planner.add_item(
+                    SyncAction::WriteLink {
+                        key: format!("item:{}", item.id),
+                        url: url.clone(),
+                        title: item.title.clone(),
+                        path,
+                    },
+                    HashMap::new(),
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+pub async fn plan_modules(config: &SyncConfig, client: &Client) -> SyncPlan {
+    let config = SyncConfig {
+        mode: SyncMode::Modules,
+        discussions: false,
+        ..config.clone()
+    };
+    plan_sync(&config, client).await
+}
+
+async fn collect_modules(config: &SyncConfig, client: &Client, planner: &Planner) {
     client
         .list_modules(config.courseid)
         .for_each(|module| async {
@@ -45,82 +984,47 @@ pub async fn download_modules(config: &SyncConfig, client: &Client, downloader:
                             match item {
                                 Ok(item) => {
                                     debug!("Item: {:?}", item);
-                                    match item.type_.as_str() {
-                                        "SubHeader" => {
-                                            indent.lock().await.add(item.indent, item);
-                                        }
-                                        "File" => {
-                                            let mut file = client
-                                                .get_course_file(
-                                                    config.courseid,
-                                                    item.content_id.expect("No content id"),
-                                                )
-                                                .await
-                                                .unwrap();
-                                            if file.url == "" {
-                                                file.url = client.build_url(
-                                                    format!(
-                                                    "/files/{}/download?download_frd=1&verifier={}",
-                                                    file.id, file.uuid
-                                                )
-                                                    .as_str(),
-                                                );
-                                                warn!(
-                                                    "No url for file: {:?}, trying to guess as {}",
-                                                    file.display_name, file.url
-                                                );
-                                            }
-                                            debug!("File: {:?}", file);
-
-                                            let mut file = File::from(file);
-
-                                            file.folder_path =
-                                                vec!["Modules".to_string(), module.name.clone()];
-                                            file.folder_path.extend(
-                                                indent
-                                                    .lock()
-                                                    .await
-                                                    .get()
-                                                    .iter()
-                                                    .map(|item| item.title.clone()),
-                                            );
-
-                                            if file.local_file_matches().unwrap_or(false) {
-                                                debug!("File already downloaded: {:?}", file);
-                                                return;
-                                            }
-
-                                            downloader.submit(file.into());
-                                        }
-                                        "ExternalUrl" | "ExternalTool" => {
-                                            if let Some(url) = item.url {
-                                                let folder_path = PathBuf::from(&config.path)
-                                                    .join("Modules")
-                                                    .join(sanitize_file_name(&module.name))
-                                                    .join(sanitize_file_name(&item.title));
-                                                write_url_file(
-                                                    &url,
-                                                    &item.title,
-                                                    folder_path.to_str().expect("Invalid path"),
-                                                )
-                                                .unwrap();
-                                            }
-                                        }
-                                        _ => {}
+                                    if item.type_ == "SubHeader" {
+                                        indent.lock().await.add(item.indent, item);
+                                        return;
                                     }
+                                    let headers = indent
+                                        .lock()
+                                        .await
+                                        .get()
+                                        .iter()
+                                        .map(|item| item.title.clone())
+                                        .collect::<Vec<_>>();
+                                    plan_module_item(
+                                        config, client, planner, &module, &headers, item,
+                                    )
+                                    .await;
+                                }
+                                Err(e) => {
+                                    planner.fail_listing("getting module items", e);
                                 }
-                                Err(e) => error!("Failed getting module items: {:?}", e),
                             }
                         })
                         .await;
                 }
-                Err(e) => error!("Failed getting modules: {:?}", e),
+                Err(e) => {
+                    planner.fail_listing("getting modules", e);
+                }
             }
         })
         .await;
 }
 
-pub async fn download_files(config: &SyncConfig, client: &Client, downloader: &Downloader) {
+pub async fn plan_files(config: &SyncConfig, client: &Client) -> SyncPlan {
+    let config = SyncConfig {
+        mode: SyncMode::Files,
+        discussions: false,
+        ..config.clone()
+    };
+    plan_sync(&config, client).await
+}
+
+async fn collect_files(config: &SyncConfig, client: &Client, planner: &Planner) {
     let folders = Mutex::new(HashMap::new());
 
     client
@@ -130,7 +1034,9 @@ pub async fn download_files(config: &SyncConfig, client: &Client, downloader: &D
                 Ok(folder) => {
                     folders.lock().await.insert(folder.id, folder.clone());
                 }
-                Err(e) => error!("Failed getting folders: {:?}", e),
+                Err(e) => {
+                    planner.fail_listing("getting folders", e);
+                }
             }
         })
         .await;
@@ -142,32 +1048,476 @@ pub async fn download_files(config: &SyncConfig, client: &Client, downloader: &D
         .for_each(|file| async {
             match file {
                 Ok(mut file) => {
-                    if file.url == "" {
-                        warn!(
-                            "No url for file: {:?}, trying to guess as {}",
-                            file.display_name, file.url
-                        );
-                        file.url = client.build_url(
-                            format!(
-                                "/files/{}/download?download_frd=1&verifier={}",
-                                file.id, file.uuid
-                            )
-                            .as_str(),
-                        );
-                    }
+                    fix_download_url(client, &mut file);
                     let folder_id = file.folder_id;
                     let mut file = File::from(file);
-                    file.set_folder_path(&folders, folder_id);
-
-                    if file.local_file_matches().unwrap_or(false) {
-                        debug!("File already exists: {:?}", file);
+                    // Its folder failed to list, which was reported already.
+                    if !file.set_folder_path(&folders, folder_id) {
+                        planner.fail_listing(
+                            format!("placing {:?}", file.file_name),
+                            format!("folder {} is not listed", folder_id),
+                        );
                         return;
                     }
+                    let segments =
+                        planner
+                            .layout
+                            .file(&file.folder_path, &file.file_name, &file.content_type);
+                    file.set_path_segments(segments);
 
-                    downloader.submit(file.into());
+                    planner.add_file(file);
+                }
+                Err(e) => {
+                    planner.fail_listing("getting files", e);
                 }
-                Err(e) => error!("Failed getting files: {:?}", e),
             }
         })
         .await;
 }
+
+async fn plan_topic(
+    config: &SyncConfig,
+    client: &Client,
+    planner: &Planner,
+    folder: &str,
+    name: &str,
+    topic: DiscussionTopicResp,
+) {
+    let view = match client.get_discussion_view(config.courseid, topic.id).await {
+        Ok(view) => view,
+        Err(e) => {
+            planner.fail_listing(format!("getting replies of {:?}", topic.title), e);
+            return;
+        }
+    };
+
+    let folder_path = vec![folder.to_string(), name.to_string()];
+    let mut attachments = topic.attachments.clone();
+    attachments.extend(view.entries().filter_map(|entry| entry.attachment.clone()));
+    for attachment in attachments {
+        let mut file = File::from(attachment);
+        file.folder_path = folder_path.clone();
+        planner.add_file(file);
+    }
+
+    let path = PathBuf::from(&config.path)
+        .join(folder)
+        .join(config.layout.sanitize.sanitize(name));
+    planner.add_item(
+        SyncAction::WriteDocument {
+            key: format!("topic:{}", topic.id),
+            body: discussion_html(&topic, &view),
+            title: topic.title,
+            path,
+            markdown: config.markdown,
+        },
+        HashMap::new(),
+    );
+}
+
+/// Plans the topics of one folder, adding the id to titles used by more than one topic.
+async fn plan_topics(
+    config: &SyncConfig,
+    client: &Client,
+    planner: &Planner,
+    folder: &str,
+    topics: impl Stream<Item = Result<DiscussionTopicResp, Error>>,
+) {
+    let topics = topics
+        .filter_map(|topic| async {
+            match topic {
+                Ok(topic) => Some(topic),
+                Err(e) => {
+                    planner.fail_listing(format!("getting {}", folder.to_lowercase()), e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>()
+        .await;
+    let title_key = |title: &str| config.layout.sanitize.sanitize(title).to_lowercase();
+    let mut counts = HashMap::<String, usize>::new();
+    for topic in &topics {
+        *counts.entry(title_key(&topic.title)).or_default() += 1;
+    }
+    for topic in topics {
+        let name = if counts[&title_key(&topic.title)] > 1 {
+            config
+                .layout
+                .sanitize
+                .sanitize_with_id(&topic.title, topic.id, false)
+        } else {
+            topic.title.clone()
+        };
+        plan_topic(config, client, planner, folder, &name, topic).await;
+    }
+}
+
+async fn collect_discussions(config: &SyncConfig, client: &Client, planner: &Planner) {
+    let discussions = client.list_discussion_topics(config.courseid);
+    plan_topics(config, client, planner, "Discussions", discussions).await;
+    let announcements = client.list_announcements(config.courseid);
+    plan_topics(config, client, planner, "Announcements", announcements).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// Serves `routes` as `(path, body)` pairs, answering anything else with a 404.
+    fn serve(routes: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let host = format!("http://{}/", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let path = path.split('?').next().unwrap_or_default();
+                let (status, body) = match routes.iter().find(|(p, _)| *p == path) {
+                    Some((_, body)) => ("200 OK", body.as_str()),
+                    None => ("404 Not Found", r#"{"errors":[{"message":"not found"}]}"#),
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        host
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("canvas-sync-{}-{}", name, std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const MODULES: &str = r#"[{"id":100,"name":"Week 1","position":1,"unlock_at":null,
+        "require_sequential_progress":false,"publish_final_grade":false,
+        "prerequisite_module_ids":[],"state":"","completed_at":null,
+        "items_count":1,"items_url":""}]"#;
+
+    fn module_item(id: i64, type_: &str, title: &str, content_id: &str, page_url: &str) -> String {
+        format!(
+            r#"{{"id":{},"module_id":100,"position":{},"title":"{}","indent":0,"type":"{}",
+                "content_id":{},"html_url":null,"url":"https://example.com/{}",
+                "page_url":"{}","external_url":null,"new_tab":null}}"#,
+            id, id, title, type_, content_id, id, page_url
+        )
+    }
+
+    fn page(title: &str) -> String {
+        format!(
+            r#"{{"page_id":1,"url":"","title":"{}","created_at":"","updated_at":"",
+                "body":"<p>{}</p>","published":true,"front_page":false,
+                "locked_for_user":false,"lock_explanation":null}}"#,
+            title, title
+        )
+    }
+
+    fn modules_config(root: &Path) -> SyncConfig {
+        SyncConfig {
+            courseid: 1,
+            path: root.to_path_buf(),
+            mode: SyncMode::Modules,
+            prune: None,
+            markdown: false,
+            discussions: false,
+            layout: Layout::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_page_fetch_prunes_nothing() {
+        let host = serve(vec![
+            ("/api/v1/courses/1/modules", MODULES.to_string()),
+            (
+                "/api/v1/courses/1/modules/100/items",
+                format!("[{}]", module_item(1, "Page", "Welcome", "null", "welcome")),
+            ),
+        ]);
+        let root = temp_dir("failed-page");
+        // A file linked from the page, downloaded by an earlier run.
+        let linked = root.join("Modules/Week 1/notes.pdf");
+        std::fs::create_dir_all(linked.parent().unwrap()).unwrap();
+        std::fs::write(&linked, "notes").unwrap();
+        let state = SyncState::load(&root);
+        state.record(FileRecord {
+            id: 11,
+            updated_at: String::new(),
+            modified_at: String::new(),
+            size: 5,
+            path: linked,
+            links: Vec::new(),
+        });
+        state.save().unwrap();
+
+        let config = SyncConfig {
+            prune: Some(PruneMode::Delete),
+            ..modules_config(&root)
+        };
+        let plan = plan_sync(&config, &Client::new(host, String::new())).await;
+
+        assert_eq!(plan.report.failed.len(), 1);
+        assert!(plan.report.failed[0].what.contains("getting page"));
+        assert!(!plan
+            .actions
+            .iter()
+            .any(|action| matches!(action, SyncAction::Delete { .. })));
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn colliding_documents_links_and_files_get_distinct_paths() {
+        let long = "a".repeat(300);
+        let items = [
+            module_item(1, "Page", "Notes", "null", "notes"),
+            module_item(2, "Page", "Notes", "null", "notes-2"),
+            module_item(3, "File", "Notes.html", "5", ""),
+            module_item(4, "ExternalUrl", "Slides", "null", ""),
+            module_item(5, "ExternalUrl", "slides", "null", ""),
+            module_item(6, "Page", "long", "null", "long"),
+            module_item(7, "Page", "long", "null", "long-2"),
+        ];
+        let host = serve(vec![
+            ("/api/v1/courses/1/modules", MODULES.to_string()),
+            (
+                "/api/v1/courses/1/modules/100/items",
+                format!("[{}]", items.join(",")),
+            ),
+            ("/api/v1/courses/1/pages/notes", page("Notes")),
+            ("/api/v1/courses/1/pages/notes-2", page("Notes")),
+            ("/api/v1/courses/1/pages/long", page(&long)),
+            ("/api/v1/courses/1/pages/long-2", page(&long)),
+            (
+                "/api/v1/courses/1/files/5",
+                r#"{"id":5,"uuid":"","folder_id":1,"display_name":"Notes.html",
+                    "filename":"Notes.html","upload_status":"success",
+                    "content-type":"text/html","url":"http://localhost/5","size":1,
+                    "created_at":"","updated_at":"","unlock_at":null,"locked":false,
+                    "hidden":false,"lock_at":null,"hidden_for_user":false,"modified_at":"",
+                    "mime_class":"html","media_entry_id":null,"locked_for_user":false}"#
+                    .to_string(),
+            ),
+        ]);
+        let root = temp_dir("colliding-items");
+        let plan = plan_sync(&modules_config(&root), &Client::new(host, String::new())).await;
+
+        assert!(plan.report.failed.is_empty());
+        let names = plan
+            .actions
+            .iter()
+            .flat_map(|action| match action {
+                SyncAction::Download { file, .. } => vec![file.local_path()],
+                _ => action.item_paths(),
+            })
+            .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let keys = names
+            .iter()
+            .map(|name| name.to_lowercase())
+            .collect::<HashSet<_>>();
+        assert_eq!(keys.len(), names.len(), "{:?}", names);
+        for name in [
+            "Notes.html",
+            "Notes (1).html",
+            "Notes (2).html",
+            "Slides.desktop",
+            "slides (5).desktop",
+        ] {
+            assert!(names.contains(&name.to_string()), "{:?}", names);
+        }
+        // The id is added after shortening the name, so it is not cut off again.
+        let long_name = names.iter().find(|name| name.ends_with(" (7).html"));
+        assert!(
+            long_name.is_some_and(|name| name.len() <= 255),
+            "{:?}",
+            names
+        );
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn renamed_item_keeps_the_copy_of_the_other_item() {
+        let host = serve(vec![
+            ("/api/v1/courses/1/modules", MODULES.to_string()),
+            (
+                "/api/v1/courses/1/modules/100/items",
+                format!(
+                    "[{},{}]",
+                    module_item(1, "Page", "Notes", "null", "notes"),
+                    module_item(2, "Page", "Notes", "null", "notes-2")
+                ),
+            ),
+            ("/api/v1/courses/1/pages/notes", page("Notes")),
+            ("/api/v1/courses/1/pages/notes-2", page("Notes")),
+        ]);
+        let root = temp_dir("renamed-item");
+        // An earlier run wrote both pages to the same file.
+        let shared = root.join("Modules/Week 1/Notes.html");
+        std::fs::create_dir_all(shared.parent().unwrap()).unwrap();
+        std::fs::write(&shared, "old").unwrap();
+        let state = SyncState::load(&root);
+        state.record_item("item:1", std::slice::from_ref(&shared));
+        state.record_item("item:2", std::slice::from_ref(&shared));
+        state.save().unwrap();
+
+        let plan = plan_sync(&modules_config(&root), &Client::new(host, String::new())).await;
+        let report = execute_plan(plan, &Downloader::new(reqwest::Client::new(), 1));
+
+        assert!(report.failed.is_empty());
+        assert!(shared.exists());
+        assert!(root.join("Modules/Week 1/Notes (2).html").exists());
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    const FOLDERS: &str = r#"[{"id":1,"name":"course files","full_name":"course files",
+        "context_id":1,"context_type":"Course","parent_folder_id":null,"created_at":"",
+        "updated_at":"","lock_at":null,"unlock_at":null,"position":null,"locked":false,
+        "folders_url":"","files_url":"","files_count":1,"folders_count":0,"hidden":null,
+        "locked_for_user":false,"hidden_for_user":false,"for_submissions":false,
+        "can_upload":false}]"#;
+
+    /// A file in the course root folder, downloaded from `/files/{id}/download`.
+    fn course_file(id: i64, name: &str, size: i64, updated_at: &str) -> String {
+        format!(
+            r#"{{"id":{},"uuid":"","folder_id":1,"display_name":"{}","filename":"{}",
+                "upload_status":"success","content-type":"application/pdf","url":"",
+                "size":{},"created_at":"","updated_at":"{}","unlock_at":null,"locked":false,
+                "hidden":false,"lock_at":null,"hidden_for_user":false,"modified_at":"",
+                "mime_class":"pdf","media_entry_id":null,"locked_for_user":false}}"#,
+            id, name, name, size, updated_at
+        )
+    }
+
+    fn files_config(root: &Path) -> SyncConfig {
+        SyncConfig {
+            mode: SyncMode::Files,
+            ..modules_config(root)
+        }
+    }
+
+    async fn sync(config: &SyncConfig, host: String) -> SyncReport {
+        let plan = plan_sync(config, &Client::new(host, String::new())).await;
+        let mut downloader = Downloader::new(reqwest::Client::new(), 1);
+        let mut report = execute_plan(plan, &downloader);
+        report.merge(downloader.finish().await);
+        report
+    }
+
+    #[tokio::test]
+    async fn changed_and_moved_file_takes_its_old_copy_along() {
+        let host = serve(vec![
+            ("/api/v1/courses/1/folders", FOLDERS.to_string()),
+            (
+                "/api/v1/courses/1/files",
+                format!("[{}]", course_file(1, "Syllabus v2.pdf", 5, "b")),
+            ),
+            ("/files/1/download", "new!!".to_string()),
+        ]);
+        let root = temp_dir("changed-and-moved");
+        let old = root.join("course files/Syllabus.pdf");
+        std::fs::create_dir_all(old.parent().unwrap()).unwrap();
+        std::fs::write(&old, "old!").unwrap();
+        let state = SyncState::load(&root);
+        state.record(FileRecord {
+            id: 1,
+            updated_at: "a".to_string(),
+            modified_at: String::new(),
+            size: 4,
+            path: old.clone(),
+            links: Vec::new(),
+        });
+        state.save().unwrap();
+
+        let report = sync(&files_config(&root), host).await;
+
+        assert!(report.failed.is_empty(), "{}", report);
+        let new = root.join("course files/Syllabus v2.pdf");
+        assert!(!old.exists());
+        assert_eq!(std::fs::read_to_string(&new).unwrap(), "new!!");
+        assert_eq!(SyncState::load(&root).get(1).unwrap().path, new);
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn pruning_leaves_a_new_file_at_the_same_path_alone() {
+        for mode in [PruneMode::Delete, PruneMode::Trash] {
+            let host = serve(vec![
+                ("/api/v1/courses/1/folders", FOLDERS.to_string()),
+                (
+                    "/api/v1/courses/1/files",
+                    format!("[{}]", course_file(2, "Syllabus.pdf", 4, "b")),
+                ),
+                ("/files/2/download", "new!".to_string()),
+            ]);
+            let root = temp_dir("prune-replaced");
+            // File 1 was removed on Canvas and file 2 uploaded under the same name.
+            let path = root.join("course files/Syllabus.pdf");
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "old!").unwrap();
+            let state = SyncState::load(&root);
+            state.record(FileRecord {
+                id: 1,
+                updated_at: "a".to_string(),
+                modified_at: String::new(),
+                size: 4,
+                path: path.clone(),
+                links: Vec::new(),
+            });
+            state.save().unwrap();
+
+            let config = SyncConfig {
+                prune: Some(mode),
+                ..files_config(&root)
+            };
+            let report = sync(&config, host).await;
+
+            assert!(report.failed.is_empty(), "{}", report);
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "new!");
+            let state = SyncState::load(&root);
+            assert!(!state.trash_dir().join("course files/Syllabus.pdf").exists());
+            assert!(state.get(1).is_none());
+            assert_eq!(state.get(2).unwrap().path, path);
+            std::fs::remove_dir_all(&root).ok();
+        }
+    }
+
+    #[tokio::test]
+    async fn file_in_unlisted_folder_is_reported() {
+        // Listing the folders fails, so the folder of the file is unknown.
+        let host = serve(vec![(
+            "/api/v1/courses/1/files",
+            format!("[{}]", course_file(1, "Syllabus.pdf", 4, "a")),
+        )]);
+        let root = temp_dir("unlisted-folder");
+        let plan = plan_sync(&files_config(&root), &Client::new(host, String::new())).await;
+
+        let failed = plan
+            .report
+            .failed
+            .iter()
+            .map(|failure| failure.what.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(failed, ["getting folders", "placing \"Syllabus.pdf\""]);
+        assert!(plan.actions.is_empty());
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::HashMap, fmt::Display, path::Path};
+
+use futures::StreamExt;
+use log::warn;
+use serde::Serialize;
+
+use crate::{
+    canvas_api::{
+        modules::{ModuleItemResp, ModuleResp},
+        Client, Error,
+    },
+    document::with_extension,
+    folder_names,
+    layout::CourseLayout,
+    state::SyncState,
+    sync::{module_item_path, IndentStack, SyncConfig},
+    File,
+};
+
+/// A folder, module or item of a course as seen on Canvas.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<String>,
+    pub locked: bool,
+    /// Whether the item exists locally, `None` for things that are not synced as a file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local: Option<bool>,
+    /// Why the item could not be looked up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeNode>,
+}
+
+impl TreeNode {
+    fn new(name: impl Into<String>, kind: &str) -> Self {
+        Self {
+            name: name.into(),
+            kind: kind.to_string(),
+            id: None,
+            size: None,
+            updated_at: None,
+            locked: false,
+            local: None,
+            error: None,
+            children: Vec::new(),
+        }
+    }
+    fn child(&mut self, name: &str, kind: &str) -> &mut TreeNode {
+        let index = match self.children.iter().position(|c| c.name == name) {
+            Some(index) => index,
+            None => {
+                self.children.push(TreeNode::new(name, kind));
+                self.children.len() - 1
+            }
+        };
+        &mut self.children[index]
+    }
+    fn descend(&mut self, names: &[String], kind: &str) -> &mut TreeNode {
+        names.iter().fold(self, |node, name| node.child(name, kind))
+    }
+    fn sort(&mut self) {
+        self.children.sort_by(|a, b| a.name.cmp(&b.name));
+        for child in &mut self.children {
+            child.sort();
+        }
+    }
+    fn fmt_indented(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        write!(f, "{:width$}{}", "", self.name, width = depth * 2)?;
+        if !self.children.is_empty() || self.kind == "folder" || self.kind == "module" {
+            write!(f, "/")?;
+        }
+        if let Some(size) = self.size {
+            write!(f, "  {} bytes", size)?;
+        }
+        match &self.updated_at {
+            Some(updated_at) if !updated_at.is_empty() => write!(f, "  {}", updated_at)?,
+            _ => {}
+        }
+        if self.locked {
+            write!(f, "  [locked]")?;
+        }
+        match self.local {
+            Some(true) => write!(f, "  [local]")?,
+            Some(false) => write!(f, "  [missing]")?,
+            None => {}
+        }
+        if let Some(error) = &self.error {
+            write!(f, "  [error: {}]", error)?;
+        }
+        writeln!(f)?;
+        for child in &self.children {
+            child.fmt_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for TreeNode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_indented(f, 0)
+    }
+}
+
+/// Whether `file` is synced where the layout puts it. The sync may have saved it under
+/// another name in that folder to keep it apart from a file of the same name, or linked
+/// it there as a copy, so the paths tracked in the sync state count too.
+fn file_is_local(file: &File, state: &SyncState) -> bool {
+    if file.local_file_matches().unwrap_or(false) {
+        return true;
+    }
+    let folder = file.local_path().parent().map(Path::to_path_buf);
+    match state.get(file.id) {
+        Some(record) if record.size == file.size => std::iter::once(&record.path)
+            .chain(&record.links)
+            .any(|path| path.parent().map(Path::to_path_buf) == folder && path.exists()),
+        _ => false,
+    }
+}
+
+fn file_node(file: &File, state: &SyncState, locked: bool) -> TreeNode {
+    let mut file = file.clone();
+    file.root = state.root().to_path_buf();
+    TreeNode {
+        id: Some(file.id),
+        size: Some(file.size),
+        updated_at: Some(file.updated_at.clone()),
+        locked,
+        local: Some(file_is_local(&file, state)),
+        ..TreeNode::new(file.file_name.clone(), "file")
+    }
+}
+
+/// Whether the document written for module item `item` exists.
+fn document_is_local(path: &Path, item: &ModuleItemResp, state: &SyncState) -> bool {
+    with_extension(path, "html").exists()
+        || state
+            .item(&format!("item:{}", item.id))
+            .iter()
+            .any(|path| path.exists())
+}
+
+/// Lists the folders and files of a course.
+pub async fn files_tree(config: &SyncConfig, client: &Client) -> Result<TreeNode, Error> {
+    let layout = CourseLayout::fetch(&config.layout, config.courseid, client).await?;
+    let state = SyncState::load(&config.path);
+    let mut folders = HashMap::new();
+    let mut stream = Box::pin(client.get_all_folders(config.courseid));
+    while let Some(folder) = stream.next().await {
+        let folder = folder?;
+        folders.insert(folder.id, folder);
+    }
+
+    let mut root = TreeNode::new("Files", "folder");
+    for folder in folders.values() {
+        let names = match folder_names(&folders, folder.id) {
+            Some(names) => names,
+            // A parent is not listed, files in it are shown with an error below.
+            None => continue,
+        };
+        let node = root.descend(&names, "folder");
+        node.id = Some(folder.id);
+        node.updated_at = Some(folder.updated_at.clone());
+        node.locked = folder.locked_for_user;
+    }
+
+    let mut stream = Box::pin(client.get_all_files(config.courseid));
+    while let Some(file) = stream.next().await {
+        let file = file?;
+        let locked = file.locked_for_user;
+        let folder_id = file.folder_id;
+        let mut file = File::from(file);
+        file.sanitize = layout.sanitize();
+        if !file.set_folder_path(&folders, folder_id) {
+            warn!("Folder {} of {:?} is not listed", folder_id, file.file_name);
+            root.children.push(TreeNode {
+                id: Some(file.id),
+                error: Some(format!("folder {} is not listed", folder_id)),
+                ..TreeNode::new(file.file_name, "file")
+            });
+            continue;
+        }
+        let folder_path = file.folder_path.clone();
+        let name = file.file_name.clone();
+        file.set_path_segments(layout.file(&folder_path, &name, &file.content_type));
+        let mut node = file_node(&file, &state, locked);
+        node.name = name;
+        root.descend(&folder_path, "folder").children.push(node);
+    }
+    root.sort();
+    Ok(root)
+}
+
+async fn module_item_node(
+    config: &SyncConfig,
+    layout: &CourseLayout,
+    state: &SyncState,
+    client: &Client,
+    module: &ModuleResp,
+    headers: &[String],
+    item: &ModuleItemResp,
+) -> Result<TreeNode, Error> {
+    let mut node = TreeNode {
+        id: item.content_id,
+        ..TreeNode::new(item.title.clone(), &item.type_.to_lowercase())
+    };
+    match item.type_.as_str() {
+        "File" => {
+            if let Some(fileid) = item.content_id {
+                let file = client.get_course_file(config.courseid, fileid).await?;
+                let locked = file.locked_for_user;
+                let mut file = File::from(file);
+                file.sanitize = layout.sanitize();
+                let name = file.file_name.clone();
+                let segments = layout.module_item(module, headers, item, &name, &file.content_type);
+                file.set_path_segments(segments);
+                node = file_node(&file, state, locked);
+                node.name = name;
+            }
+        }
+        "Page" => {
+            if let Some(page_url) = &item.page_url {
+                let page = client.get_course_page(config.courseid, page_url).await?;
+                let path = module_item_path(config, layout, module, headers, item, &page.title);
+                node.name = page.title;
+                node.updated_at = Some(page.updated_at);
+                node.locked = page.locked_for_user;
+                node.local = Some(document_is_local(&path, item, state));
+            }
+        }
+        "Assignment" => {
+            if let Some(assignmentid) = item.content_id {
+                let assignment = client
+                    .get_course_assignment(config.courseid, assignmentid)
+                    .await?;
+                let path =
+                    module_item_path(config, layout, module, headers, item, &assignment.name);
+                node.name = assignment.name;
+                node.updated_at = Some(assignment.updated_at);
+                node.locked = assignment.locked_for_user.unwrap_or(false);
+                node.local = Some(document_is_local(&path, item, state));
+            }
+        }
+        _ => {}
+    }
+    Ok(node)
+}
+
+fn header_titles(indent: &IndentStack<ModuleItemResp>) -> Vec<String> {
+    indent.get().iter().map(|item| item.title.clone()).collect()
+}
+
+/// Lists the modules of a course and their items, nested under their subheaders.
+pub async fn modules_tree(config: &SyncConfig, client: &Client) -> Result<TreeNode, Error> {
+    let layout = CourseLayout::fetch(&config.layout, config.courseid, client).await?;
+    let state = SyncState::load(&config.path);
+    let mut root = TreeNode::new("Modules", "folder");
+    let mut modules = Box::pin(client.list_modules(config.courseid));
+    while let Some(module) = modules.next().await {
+        let module = module?;
+        let mut module_node = TreeNode {
+            id: Some(module.id),
+            locked: module.state == "locked",
+            ..TreeNode::new(module.name.clone(), "module")
+        };
+
+        let mut indent = IndentStack::new();
+        let mut items = Box::pin(client.list_module_items(config.courseid, module.id));
+        while let Some(item) = items.next().await {
+            let item = item?;
+            if item.type_ == "SubHeader" {
+                indent.add(item.indent, item);
+                module_node.descend(&header_titles(&indent), "subheader");
+                continue;
+            }
+            let headers = header_titles(&indent);
+            // A single locked or unpublished item should not hide the rest of the course.
+            let node =
+                match module_item_node(config, &layout, &state, client, &module, &headers, &item)
+                    .await
+                {
+                    Ok(node) => node,
+                    Err(e) => {
+                        warn!("Failed getting {:?}: {}", item.title, e);
+                        TreeNode {
+                            id: item.content_id,
+                            error: Some(e.to_string()),
+                            ..TreeNode::new(item.title.clone(), &item.type_.to_lowercase())
+                        }
+                    }
+                };
+            module_node
+                .descend(&headers, "subheader")
+                .children
+                .push(node);
+        }
+        root.children.push(module_node);
+    }
+    Ok(root)
+}